@@ -6,6 +6,9 @@ declare -a files=(
     "umask_prg=umask.c"
     "failure_prg=failure.c"
     "wait_prg=wait.c"
+    "fork_prg=fork.c"
+    "listen_prg=listen.c"
+    "notify_prg=notify.c"
 )
 
 for file in "${files[@]}"; do
@@ -0,0 +1,243 @@
+//! Parses the command-line arguments of the program.
+
+use std::{fmt::Display, path::PathBuf};
+
+/// The default path of the configuration file.
+pub const CONFIG_DEFAULT_PATH: &str = "config/run.yml";
+/// The default path of the log file.
+pub const LOG_DEFAULT_PATH: &str = "taskmaster.log";
+/// The default path of the pidfile used in daemon mode.
+pub const PIDFILE_DEFAULT_PATH: &str = "taskmaster.pid";
+
+/// The usage message printed by `--help`.
+const USAGE: &str = "\
+Usage: taskmaster [OPTIONS]
+
+Options:
+  -c, --config <PATH>    The configuration file to load (default: config/run.yml)
+  -l, --log-file <PATH>  The file to write logs to (default: taskmaster.log)
+      --no-shell         Supervise the programs without starting the interactive shell
+  -d, --daemon           Detach from the terminal and run in the background (implies --no-shell)
+  -p, --pidfile <PATH>   The pidfile to write and lock (default in daemon mode: taskmaster.pid)
+  -V, --version          Print version information and exit
+  -h, --help             Print this message and exit";
+
+/// What the program has been asked to do.
+#[derive(Debug)]
+pub enum Action {
+    /// Run the supervisor.
+    Run(Args),
+    /// Print the help message and exit.
+    Help,
+    /// Print the version and exit.
+    Version,
+}
+
+/// The options of the supervisor.
+#[derive(Debug)]
+pub struct Args {
+    /// The path to the configuration file.
+    pub config: PathBuf,
+    /// The path to the log file.
+    pub log_file: PathBuf,
+    /// Whether the interactive shell should be started.
+    pub shell: bool,
+    /// Whether the supervisor should detach from its terminal.
+    pub daemon: bool,
+    /// The path to the pidfile, if any.
+    pub pidfile: Option<PathBuf>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            config: PathBuf::from(CONFIG_DEFAULT_PATH),
+            log_file: PathBuf::from(LOG_DEFAULT_PATH),
+            shell: true,
+            daemon: false,
+            pidfile: None,
+        }
+    }
+}
+
+/// An error that can occur while parsing the command-line arguments.
+#[derive(Debug)]
+pub enum ArgsError {
+    /// An option that is not known was provided.
+    UnknownOption(String),
+    /// An option that expects a value was provided without one.
+    MissingValue(&'static str),
+    /// A value was provided to an option that does not expect one.
+    UnexpectedValue(&'static str),
+    /// A positional argument was provided.
+    UnexpectedArgument(String),
+}
+
+impl Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::UnknownOption(opt) => write!(f, "unknown option `{opt}`"),
+            ArgsError::MissingValue(opt) => write!(f, "option `{opt}` expects a value"),
+            ArgsError::UnexpectedValue(opt) => write!(f, "option `{opt}` does not take a value"),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "unexpected argument `{arg}`"),
+        }
+    }
+}
+
+impl Action {
+    /// Parses the arguments passed to the program.
+    pub fn from_env() -> Result<Self, ArgsError> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parses the provided arguments, not including the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut result = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Support both `--option value` and `--option=value`.
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if arg.starts_with("--") => {
+                    (option.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+
+            let mut value = |name: &'static str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(ArgsError::MissingValue(name))
+            };
+            let flag = |name: &'static str| match inline_value {
+                Some(_) => Err(ArgsError::UnexpectedValue(name)),
+                None => Ok(()),
+            };
+
+            match option.as_str() {
+                "-c" | "--config" => result.config = value("--config")?.into(),
+                "-l" | "--log-file" => result.log_file = value("--log-file")?.into(),
+                "--no-shell" => {
+                    flag("--no-shell")?;
+                    result.shell = false;
+                }
+                "-d" | "--daemon" => {
+                    flag("--daemon")?;
+                    result.daemon = true;
+                }
+                "-p" | "--pidfile" => result.pidfile = Some(value("--pidfile")?.into()),
+                "-V" | "--version" => {
+                    flag("--version")?;
+                    return Ok(Action::Version);
+                }
+                "-h" | "--help" => {
+                    flag("--help")?;
+                    return Ok(Action::Help);
+                }
+                _ if option.starts_with('-') => return Err(ArgsError::UnknownOption(option)),
+                _ => return Err(ArgsError::UnexpectedArgument(option)),
+            }
+        }
+
+        if result.daemon {
+            result.shell = false;
+            result
+                .pidfile
+                .get_or_insert_with(|| PathBuf::from(PIDFILE_DEFAULT_PATH));
+        }
+
+        Ok(Action::Run(result))
+    }
+}
+
+/// Prints the usage message.
+pub fn print_help() {
+    println!("{USAGE}");
+}
+
+/// Prints the version of the program.
+pub fn print_version() {
+    println!("taskmaster {}", env!("CARGO_PKG_VERSION"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Action, ArgsError> {
+        Action::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run(args: &[&str]) -> Args {
+        match parse(args) {
+            Ok(Action::Run(args)) => args,
+            other => panic!("expected to run, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let args = run(&[]);
+        assert_eq!(args.config, PathBuf::from(CONFIG_DEFAULT_PATH));
+        assert_eq!(args.log_file, PathBuf::from(LOG_DEFAULT_PATH));
+        assert!(args.shell);
+        assert!(!args.daemon);
+        assert_eq!(args.pidfile, None);
+    }
+
+    #[test]
+    fn separate_and_inline_values() {
+        let args = run(&["-c", "a.yml", "--log-file=b.log", "--pidfile", "c.pid"]);
+        assert_eq!(args.config, PathBuf::from("a.yml"));
+        assert_eq!(args.log_file, PathBuf::from("b.log"));
+        assert_eq!(args.pidfile, Some(PathBuf::from("c.pid")));
+    }
+
+    #[test]
+    fn inline_value_keeps_equal_signs() {
+        assert_eq!(run(&["--config=a=b.yml"]).config, PathBuf::from("a=b.yml"));
+    }
+
+    #[test]
+    fn daemon_implies_no_shell_and_pidfile() {
+        let args = run(&["-d"]);
+        assert!(args.daemon);
+        assert!(!args.shell);
+        assert_eq!(args.pidfile, Some(PathBuf::from(PIDFILE_DEFAULT_PATH)));
+
+        let args = run(&["-p", "x.pid", "--daemon"]);
+        assert_eq!(args.pidfile, Some(PathBuf::from("x.pid")));
+    }
+
+    #[test]
+    fn no_shell() {
+        assert!(!run(&["--no-shell"]).shell);
+    }
+
+    #[test]
+    fn help_and_version_stop_parsing() {
+        assert!(matches!(parse(&["-h", "--bogus"]), Ok(Action::Help)));
+        assert!(matches!(parse(&["--version"]), Ok(Action::Version)));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            parse(&["--bogus"]),
+            Err(ArgsError::UnknownOption(opt)) if opt == "--bogus"
+        ));
+        assert!(matches!(
+            parse(&["-c"]),
+            Err(ArgsError::MissingValue("--config"))
+        ));
+        assert!(matches!(
+            parse(&["--daemon=yes"]),
+            Err(ArgsError::UnexpectedValue("--daemon"))
+        ));
+        assert!(matches!(
+            parse(&["config.yml"]),
+            Err(ArgsError::UnexpectedArgument(arg)) if arg == "config.yml"
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+//! A client sending commands to a running supervisor through its control socket.
+//!
+//! Without a command, an interactive shell is started. Otherwise, the command is sent once and
+//! the exit code reflects whether it succeeded.
+
+use std::{
+    ffi::c_int,
+    io::BufReader,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use taskmaster::protocol::{self, Reply, DEFAULT_SOCKET_PATH};
+
+/// The usage message printed by `--help`.
+const USAGE: &str = "\
+Usage: taskmasterctl [OPTIONS] [COMMAND [ARGS...]]
+
+Sends COMMAND to a running supervisor, or starts an interactive shell when no command is given.
+
+Commands:
+  status [target...], start <target...>, stop <target...>, restart <target...>,
+  signal <signal> <target...>, logs <target...> [-n N] [-f] [--stderr], reload, reopen-logs,
+  shutdown
+
+  `logs -f` keeps printing new lines until interrupted.
+
+Targets:
+  web, web-1, web:1, worker-*, group:backend, all
+
+Options:
+  -s, --socket <PATH>  The control socket of the supervisor (default: taskmaster.sock)
+  -V, --version        Print version information and exit
+  -h, --help           Print this message and exit
+
+Exit status:
+  0  the command succeeded
+  1  the command failed
+  2  the supervisor could not be reached, or the usage was incorrect";
+
+/// The command succeeded.
+const EXIT_SUCCESS: u8 = 0;
+/// The supervisor reported that the command failed.
+const EXIT_FAILURE: u8 = 1;
+/// The client could not talk to the supervisor.
+const EXIT_ERROR: u8 = 2;
+
+/// The socket of a command whose output is being streamed, or -1.
+static STREAMING: AtomicI32 = AtomicI32::new(-1);
+
+/// Stops the output being streamed, or terminates the client as usual otherwise.
+extern "C" fn interrupt(_signal: c_int) {
+    // Swapped, so that the stream is only asked to stop once.
+    match STREAMING.swap(-1, Ordering::SeqCst) {
+        -1 => unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        },
+        fd => unsafe {
+            libc::write(fd, b"\n".as_ptr().cast(), 1);
+        },
+    }
+}
+
+/// A connection to the supervisor.
+struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connects to the control socket at `path`.
+    fn connect(path: &Path) -> std::io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    /// Sends a command and waits for its reply.
+    ///
+    /// The output streamed before the reply is printed as it arrives.
+    fn send(&mut self, line: &str) -> std::io::Result<Reply> {
+        protocol::write_request(&mut self.writer, line)?;
+
+        let fd = self.writer.as_raw_fd();
+        let mut streaming = false;
+        let reply = protocol::read_streamed_reply(&mut self.reader, |lines| {
+            if !streaming {
+                streaming = true;
+                STREAMING.store(fd, Ordering::SeqCst);
+            }
+            for line in lines {
+                println!("{line}");
+            }
+        });
+        STREAMING.store(-1, Ordering::SeqCst);
+        reply
+    }
+}
+
+/// Prints a reply, sending the output of failed commands to the standard error.
+fn print_reply(reply: &Reply) {
+    for line in &reply.lines {
+        if reply.success {
+            println!("{line}");
+        } else {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Runs a single command.
+fn run_once(client: &mut Client, line: &str) -> u8 {
+    match client.send(line) {
+        Ok(reply) => {
+            print_reply(&reply);
+            if reply.success {
+                EXIT_SUCCESS
+            } else {
+                EXIT_FAILURE
+            }
+        }
+        Err(err) => {
+            eprintln!("\x1B[1;31merror\x1B[0m: lost connection to the supervisor: {err}");
+            EXIT_ERROR
+        }
+    }
+}
+
+/// Runs the interactive shell until the standard input is closed.
+fn run_shell(client: &mut Client) -> u8 {
+    let mut readline = ft::readline::Readline::new();
+
+    while readline.read().unwrap() {
+        readline.history_add_buffer().unwrap();
+        println!();
+
+        let line = readline.buffer().trim();
+        match line {
+            "" => continue,
+            "exit" | "quit" => break,
+            _ => (),
+        }
+
+        if run_once(client, line) == EXIT_ERROR {
+            return EXIT_ERROR;
+        }
+    }
+
+    EXIT_SUCCESS
+}
+
+fn main() -> std::process::ExitCode {
+    let mut socket = PathBuf::from(DEFAULT_SOCKET_PATH);
+    let mut args = std::env::args().skip(1);
+    let mut command = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" | "--socket" => match args.next() {
+                Some(path) => socket = path.into(),
+                None => {
+                    eprintln!("\x1B[1;31merror\x1B[0m: option `--socket` expects a value");
+                    return EXIT_ERROR.into();
+                }
+            },
+            "-V" | "--version" => {
+                println!("taskmasterctl {}", env!("CARGO_PKG_VERSION"));
+                return EXIT_SUCCESS.into();
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return EXIT_SUCCESS.into();
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("\x1B[1;31merror\x1B[0m: unknown option `{arg}`");
+                eprintln!("Try `--help` for more information.");
+                return EXIT_ERROR.into();
+            }
+            _ => {
+                // Everything from the first positional argument on is the command.
+                command.push(arg);
+                command.extend(args.by_ref());
+            }
+        }
+    }
+
+    let mut client = match Client::connect(&socket) {
+        Ok(ok) => ok,
+        Err(err) => {
+            eprintln!(
+                "\x1B[1;31merror\x1B[0m: can't connect to `{}`: {err}",
+                socket.display()
+            );
+            return EXIT_ERROR.into();
+        }
+    };
+
+    unsafe { libc::signal(libc::SIGINT, interrupt as *const () as libc::sighandler_t) };
+
+    if command.is_empty() {
+        run_shell(&mut client).into()
+    } else {
+        run_once(&mut client, &command.join(" ")).into()
+    }
+}
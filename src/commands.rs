@@ -1,80 +1,367 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeSet,
+    sync::{mpsc, Arc, RwLock},
+    time::Duration,
+};
 
 use crate::{
     config::{Config, ConfigDiff},
-    program::{Process, ProcessName},
-    Taskmaster, CONFIG_DEFAULT_PATH,
+    logs::{self, LogMessage},
+    output::{self, FollowReceiver, OutputMessage, Stream},
+    program::{Process, ProcessError, ProcessName},
+    signal::Signal,
+    ShutdownReason, Taskmaster,
 };
+use taskmaster::protocol::Reply;
 
-pub fn status(line: &str, taskmaster: &Taskmaster) {
-    let _ = (line, taskmaster);
-    for process in taskmaster.processes.iter() {
-        match process.state.pid.lock().unwrap().as_ref() {
-            Some(content) => {
-                println!("{:<12} | {:<6} | running", process.name().name, content.pid)
-            }
-            None => {
-                println!("{:<12} | {:6} | not running", process.name().name, "");
-            }
+fn split_whitespace(s: &str) -> (&str, &str) {
+    let index = s.find(char::is_whitespace).unwrap_or(s.len());
+    s.split_at(index)
+}
+
+/// The number of lines printed by `logs` by default.
+const DEFAULT_LOG_LINES: usize = 10;
+
+/// Executes a command line, as typed in the shell or received on the control socket.
+///
+/// When the command follows the output of processes, the receiver of their next lines is
+/// returned along with the reply.
+pub fn execute(taskmaster: &RwLock<Taskmaster>, mut line: &str) -> (Reply, Option<FollowReceiver>) {
+    let mut reply = Reply::new();
+    let mut follow = None;
+
+    let command;
+    (command, line) = split_whitespace(line.trim());
+    line = line.trim();
+
+    match command {
+        "start" => start(line, &taskmaster.read().unwrap(), &mut reply),
+        "stop" => stop(line, &taskmaster.read().unwrap(), &mut reply),
+        "restart" => restart(line, &taskmaster.read().unwrap(), &mut reply),
+        "signal" => signal(line, &taskmaster.read().unwrap(), &mut reply),
+        "status" => status(line, &taskmaster.read().unwrap(), &mut reply),
+        "reload" => reload(line, taskmaster, &mut reply),
+        "shutdown" => shutdown(line, &taskmaster.read().unwrap(), &mut reply),
+        "reopen-logs" => reopen_logs(line, taskmaster, &mut reply),
+        "logs" => follow = logs(line, &taskmaster.read().unwrap(), &mut reply),
+        "" => (),
+        _ => reply.fail(format!("Unknown command: {}", command)),
+    }
+
+    (reply, follow)
+}
+
+/// Formats a duration as `[<days>d ]HH:MM:SS`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days == 0 {
+        format!("{hours:02}:{mins:02}:{secs:02}")
+    } else {
+        format!("{days}d {hours:02}:{mins:02}:{secs:02}")
+    }
+}
+
+pub fn status(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    let processes = if line.is_empty() {
+        taskmaster.processes.iter().collect()
+    } else {
+        match resolve_targets(line, taskmaster, reply) {
+            Some(processes) => processes,
+            None => return,
+        }
+    };
+
+    reply.push(format!(
+        "{:<16} | {:<9} | {:<7} | {:>11} | {:>11} | {:>7} | {:<28} | STATUS",
+        "NAME", "STATE", "PID", "UPTIME", "IN STATE", "RETRIES", "LAST EXIT"
+    ));
+
+    for process in processes {
+        let lifecycle = process.state.lifecycle.lock().unwrap();
+
+        let (pid, uptime) = match &lifecycle.running {
+            Some(running) => (
+                running.pid.to_string(),
+                format_duration(running.started_at.elapsed()),
+            ),
+            None => ("-".into(), "-".into()),
+        };
+        let last_exit = match lifecycle.last_exit {
+            Some(status) => status.to_string(),
+            None => "-".into(),
+        };
+
+        reply.push(format!(
+            "{:<16} | {:<9} | {:<7} | {:>11} | {:>11} | {:>7} | {:<28} | {}",
+            process.name(),
+            lifecycle.status,
+            pid,
+            uptime,
+            format_duration(lifecycle.since.elapsed()),
+            lifecycle.retry_count,
+            last_exit,
+            lifecycle.status_text.as_deref().unwrap_or("-"),
+        ));
+
+        for pid in &lifecycle.strays {
+            reply.push(format!(
+                "{:<16} | {:<9} | {:<7} | {:>11} | {:>11} | {:>7} | {:<28} | -",
+                "  (stray)", "ADOPTED", pid, "-", "-", "-", "-"
+            ));
         }
     }
 }
 
-pub fn start(line: &str, taskmaster: &Taskmaster) {
-    if taskmaster.get_processes_by_name(line).next().is_none() {
-        println!("Process not found");
-        return;
+/// Resolves the targets of a command, failing the reply if there are none or if one of them
+/// designates no process.
+fn resolve_targets<'a>(
+    line: &str,
+    taskmaster: &'a Taskmaster,
+    reply: &mut Reply,
+) -> Option<Vec<&'a Process>> {
+    if line.is_empty() {
+        reply.fail("Missing target");
+        return None;
     }
-    for process in taskmaster.get_processes_by_name(line) {
-        if let Err(err) = process.launch() {
-            println!("Error: {}", err);
+    match taskmaster.get_processes_by_targets(line) {
+        Ok(processes) => Some(processes),
+        Err(target) => {
+            reply.fail(format!("Process not found: {target}"));
+            None
         }
     }
 }
 
-pub fn stop(line: &str, taskmaster: &Taskmaster) {
-    if taskmaster.get_processes_by_name(line).next().is_none() {
-        println!("Process not found");
+pub fn start(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    let Some(processes) = resolve_targets(line, taskmaster, reply) else {
         return;
+    };
+    for process in processes {
+        if let Err(err) = taskmaster.launch(process) {
+            reply.fail(format!("Error: {}: {}", process.name(), err));
+        }
     }
-    for process in taskmaster.get_processes_by_name(line) {
+}
+
+pub fn stop(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    let Some(processes) = resolve_targets(line, taskmaster, reply) else {
+        return;
+    };
+    for process in processes {
         if let Err(err) = process.request_stop() {
-            println!("Error: {}", err);
+            reply.fail(format!("Error: {}: {}", process.name(), err));
         }
     }
 }
 
-pub fn restart(line: &str, taskmaster: &Taskmaster) {
-    if taskmaster.get_processes_by_name(line).next().is_none() {
-        println!("Process not found");
+pub fn restart(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    let Some(processes) = resolve_targets(line, taskmaster, reply) else {
         return;
-    }
-    for process in taskmaster.get_processes_by_name(line) {
+    };
+    for process in processes {
         if let Err(err) = process.request_restart() {
-            println!("Error: {}", err);
+            reply.fail(format!("Error: {}: {}", process.name(), err));
         }
     }
 }
 
-pub fn reload(_line: &str, taskmaster: &mut Taskmaster) {
-    let new_config = match Config::parse(CONFIG_DEFAULT_PATH.as_ref()) {
+/// Sends a signal to the processes designated by targets, as in `signal SIGUSR1 web`.
+///
+/// The signal only goes to the main process of each, not to its process group.
+pub fn signal(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    let (signal, targets) = split_whitespace(line);
+    if signal.is_empty() {
+        reply.fail("Usage: signal <signal> <target>...");
+        return;
+    }
+
+    let signal: Signal = match signal.parse() {
+        Ok(ok) => ok,
+        Err(err) => {
+            reply.fail(format!("Error: {}", err));
+            return;
+        }
+    };
+
+    let Some(processes) = resolve_targets(targets.trim(), taskmaster, reply) else {
+        return;
+    };
+
+    // Processes that are not running are skipped, unless none of them is.
+    let mut sent = 0;
+    for process in processes {
+        match process.state.send_signal(signal) {
+            Ok(()) => sent += 1,
+            Err(ProcessError::NotStarted) => (),
+            Err(err) => reply.fail(format!("Error: {}: {}", process.name(), err)),
+        }
+    }
+    if sent == 0 {
+        reply.fail(format!("Error: {}", ProcessError::NotStarted));
+    }
+}
+
+/// The options of `logs`.
+#[derive(Debug, PartialEq, Eq)]
+struct LogsOptions<'a> {
+    /// How many of the last lines to print.
+    count: usize,
+    /// Whether to follow the next lines.
+    follow: bool,
+    stream: Stream,
+    targets: Vec<&'a str>,
+}
+
+impl<'a> LogsOptions<'a> {
+    /// Parses the arguments of `logs`, as in `logs -f -n 20 --stderr web`.
+    fn parse(line: &'a str) -> Result<Self, String> {
+        let mut options = Self {
+            count: DEFAULT_LOG_LINES,
+            follow: false,
+            stream: Stream::Stdout,
+            targets: Vec::new(),
+        };
+
+        let mut words = line.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "-n" => match words.next().and_then(|count| count.parse().ok()) {
+                    Some(n) => options.count = n,
+                    None => return Err("Option `-n` expects a number of lines".to_owned()),
+                },
+                "-f" | "--follow" => options.follow = true,
+                "--stderr" => options.stream = Stream::Stderr,
+                _ if word.starts_with('-') => return Err(format!("Unknown option: {word}")),
+                _ => options.targets.push(word),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Prints the last lines written by processes, and follows the next ones with `-f`.
+pub fn logs(line: &str, taskmaster: &Taskmaster, reply: &mut Reply) -> Option<FollowReceiver> {
+    let LogsOptions {
+        count,
+        follow,
+        stream,
+        targets,
+    } = match LogsOptions::parse(line) {
+        Ok(options) => options,
+        Err(err) => {
+            reply.fail(err);
+            return None;
+        }
+    };
+
+    let processes = resolve_targets(&targets.join(" "), taskmaster, reply)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut lines = Vec::new();
+    for process in processes {
+        // The lines are collected and followed at once, so that none is missed or repeated.
+        let mut output = process.state.output.lock().unwrap();
+        let buffered = output.lines(stream);
+        let skipped = buffered.len().saturating_sub(count);
+        lines.extend(buffered.iter().skip(skipped).cloned());
+        if follow {
+            output.follow(stream, sender.clone());
+        }
+    }
+
+    // The sort is stable, so lines read at the same time keep their order.
+    lines.sort_by_key(|line| line.time);
+    for line in &lines[lines.len().saturating_sub(count)..] {
+        reply.push(line.to_string());
+    }
+
+    follow.then_some(receiver)
+}
+
+pub fn shutdown(_line: &str, taskmaster: &Taskmaster, reply: &mut Reply) {
+    taskmaster.request_shutdown(ShutdownReason::Command);
+    reply.push("Shutting down");
+}
+
+/// Reopens the log files, so that they can be rotated by an external tool.
+pub fn reopen_logs(_line: &str, taskmaster: &RwLock<Taskmaster>, reply: &mut Reply) {
+    let (log_sender, output_sender) = {
+        let taskmaster = taskmaster.read().unwrap();
+        (
+            taskmaster.log_sender.clone(),
+            taskmaster.output_sender.clone(),
+        )
+    };
+
+    // The lock is released first, as the log thread takes it.
+    let mut errors = logs::reopen(&log_sender);
+    errors.extend(output::reopen(&output_sender));
+
+    if errors.is_empty() {
+        reply.push("Log files reopened");
+    }
+    for err in errors {
+        reply.fail(err);
+    }
+}
+
+/// Reloads the configuration file, and applies its changes.
+///
+/// The processes of the programs that are removed or modified are stopped gracefully first, and
+/// the lock is released meanwhile, as the reactor needs it to notice that they exit.
+pub fn reload(_line: &str, taskmaster: &RwLock<Taskmaster>, reply: &mut Reply) {
+    let config_path = taskmaster.read().unwrap().config_path.clone();
+    let new_config = match Config::parse(&config_path) {
         Ok(config) => config,
         Err(err) => {
-            println!("\x1B[1;31merror\x1B[0m: can't reload config: {err}");
+            reply.fail(format!("Error: can't reload config: {err}"));
             return;
         }
     };
+
+    let outgoing: BTreeSet<String> = new_config
+        .diff_since(&taskmaster.read().unwrap().config)
+        .into_iter()
+        .filter_map(|diff| match diff {
+            ConfigDiff::RemovedProgram(name) | ConfigDiff::ModifiedProgram(name, _) => Some(name),
+            _ => None,
+        })
+        .collect();
+    crate::stop_programs(taskmaster, &outgoing);
+
+    let taskmaster = &mut *taskmaster.write().unwrap();
+    // The configuration may have been reloaded while the processes were stopping.
     let diff = new_config.diff_since(&taskmaster.config);
 
+    let rotation_changed = new_config.log_rotation != taskmaster.config.log_rotation;
+    if rotation_changed {
+        reply.push("updating log rotation");
+        let rotation = new_config.log_rotation;
+        let _ = taskmaster
+            .log_sender
+            .send(LogMessage::SetRotation(rotation));
+        let _ = taskmaster
+            .output_sender
+            .send(OutputMessage::SetRotation(rotation));
+        taskmaster.config.log_rotation = rotation;
+    }
+
     if diff.is_empty() {
-        println!("No changes");
+        if !rotation_changed {
+            reply.push("No changes");
+        }
         return;
     }
 
+    // The programs to start once every change has been applied, so that their dependencies are
+    // known.
+    let mut to_launch = BTreeSet::new();
+
     for diff in diff {
         match diff {
             ConfigDiff::AddedProgram(name, config) => {
-                println!("adding `{name}`");
+                reply.push(format!("adding `{name}`"));
 
                 for replica_index in 0..config.replicas {
                     let name = ProcessName {
@@ -82,23 +369,22 @@ pub fn reload(_line: &str, taskmaster: &mut Taskmaster) {
                         index: replica_index,
                     };
 
-                    println!("adding replica `{name}`");
+                    reply.push(format!("adding replica `{name}`"));
                     taskmaster.processes.push(Process::new(
                         taskmaster.log_sender.clone(),
+                        taskmaster.output_sender.clone(),
+                        taskmaster.reactor.clone(),
                         name.clone(),
                         config.clone(),
                     ));
+                }
 
-                    if config.at_launch {
-                        let _ = taskmaster
-                            .get_process_by_process_name(&name)
-                            .unwrap()
-                            .launch();
-                    }
+                if config.at_launch {
+                    to_launch.insert(name);
                 }
             }
             ConfigDiff::ModifiedProgram(name, config) => {
-                println!("reloading `{name}`");
+                reply.push(format!("reloading `{name}`"));
 
                 taskmaster
                     .processes
@@ -111,27 +397,101 @@ pub fn reload(_line: &str, taskmaster: &mut Taskmaster) {
                     };
                     taskmaster.processes.push(Process::new(
                         taskmaster.log_sender.clone(),
+                        taskmaster.output_sender.clone(),
+                        taskmaster.reactor.clone(),
                         name.clone(),
                         config.clone(),
                     ));
+                }
 
-                    if config.at_launch {
-                        let _ = taskmaster
-                            .get_process_by_process_name(&name)
-                            .unwrap()
-                            .launch();
-                    }
+                if config.at_launch {
+                    to_launch.insert(name);
                 }
             }
             ConfigDiff::RemovedProgram(name) => {
-                println!("removing `{name}`");
+                reply.push(format!("removing `{name}`"));
 
                 taskmaster
                     .processes
-                    .retain(|p| p.name().name.as_ref() == name.as_str());
+                    .retain(|p| p.name().name.as_ref() != name.as_str());
             }
+            ConfigDiff::AddedGroup(name) => reply.push(format!("adding group `{name}`")),
+            ConfigDiff::RemovedGroup(name) => reply.push(format!("removing group `{name}`")),
+            ConfigDiff::ModifiedGroup(name) => reply.push(format!("restarting group `{name}`")),
         }
     }
 
     taskmaster.config = new_config;
+
+    for name in taskmaster.config.start_order() {
+        if to_launch.contains(name) {
+            for process in taskmaster.get_processes_by_name(name) {
+                let _ = taskmaster.launch(process);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> LogsOptions<'_> {
+        match LogsOptions::parse(line) {
+            Ok(options) => options,
+            Err(err) => panic!("expected options, got {err:?}"),
+        }
+    }
+
+    #[test]
+    fn logs_defaults() {
+        assert_eq!(
+            parse("web"),
+            LogsOptions {
+                count: DEFAULT_LOG_LINES,
+                follow: false,
+                stream: Stream::Stdout,
+                targets: vec!["web"],
+            }
+        );
+        assert!(parse("").targets.is_empty());
+    }
+
+    #[test]
+    fn logs_follow() {
+        assert!(parse("-f web").follow);
+        assert!(parse("web --follow").follow);
+    }
+
+    #[test]
+    fn logs_stream() {
+        assert_eq!(parse("--stderr web").stream, Stream::Stderr);
+    }
+
+    #[test]
+    fn logs_count() {
+        assert_eq!(parse("-n 3 web").count, 3);
+        assert_eq!(parse("-n 0 web").count, 0);
+    }
+
+    #[test]
+    fn logs_targets_between_options() {
+        let options = parse("web:0 -f worker --stderr -n 5 db");
+        assert_eq!(options.targets, ["web:0", "worker", "db"]);
+        assert!(options.follow);
+        assert_eq!(options.stream, Stream::Stderr);
+        assert_eq!(options.count, 5);
+    }
+
+    #[test]
+    fn logs_errors() {
+        let err = "Option `-n` expects a number of lines";
+        assert_eq!(LogsOptions::parse("-n"), Err(err.to_owned()));
+        assert_eq!(LogsOptions::parse("-n many web"), Err(err.to_owned()));
+        assert_eq!(LogsOptions::parse("-n -1 web"), Err(err.to_owned()));
+        assert_eq!(
+            LogsOptions::parse("--stdout web"),
+            Err("Unknown option: --stdout".to_owned())
+        );
+    }
 }
@@ -1,10 +1,16 @@
 use serde::Deserialize;
+
+use crate::{output::Stream, signal::Signal};
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     error::Error,
     path::{Path, PathBuf},
 };
 
+/// The longest duration accepted in the configuration, in seconds, which is a year, so that the
+/// deadlines computed from durations cannot overflow.
+const MAX_DURATION: f64 = 365.0 * 24.0 * 3600.0;
+
 /// Deserializes a `umask` from a string as an octal number.
 fn deserialize_umask<'de, D>(deserializer: D) -> Result<Option<libc::mode_t>, D::Error>
 where
@@ -47,43 +53,82 @@ pub enum RestartPolicy {
     OnFailure,
 }
 
-/// The signal to send to a process to stop it.
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
-pub enum StopSignal {
-    #[serde(rename = "SIGINT")]
+/// A step of the sequence used to stop a process.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct StopStep {
+    /// The signal to send to the process.
+    pub signal: Signal,
+    /// The amount of time to wait for the process to exit before moving on to the next step.
+    pub timeout: f64,
+}
+
+/// How the health of a process is checked.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// Runs a command, which must exit with code 0.
+    Exec(Vec<String>),
+    /// Connects to a TCP address, such as `127.0.0.1:8080`.
+    Tcp(String),
+    /// Sends a `GET` request to a URL, which must answer with a success status.
+    Http(String),
+}
+
+/// What to do when a process becomes unhealthy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthAction {
+    /// Only report the process as unhealthy.
     #[default]
-    Int,
-    #[serde(rename = "SIGTERM")]
-    Term,
-    #[serde(rename = "SIGHUP")]
-    Hup,
-    #[serde(rename = "SIGQUIT")]
-    Quit,
-    #[serde(rename = "SIGKILL")]
-    Kill,
-    #[serde(rename = "SIGUSR1")]
-    Usr1,
-    #[serde(rename = "SIGUSR2")]
-    Usr2,
-    #[serde(rename = "SIGSTOP")]
-    Stop,
-    #[serde(rename = "SIGALRM")]
-    Alarm,
+    None,
+    /// Restart the process.
+    Restart,
 }
 
-impl StopSignal {
-    /// Returns the raw signal number for the signal.
-    pub fn as_raw_signal(&self) -> libc::c_int {
-        match self {
-            StopSignal::Hup => libc::SIGHUP,
-            StopSignal::Int => libc::SIGINT,
-            StopSignal::Quit => libc::SIGQUIT,
-            StopSignal::Term => libc::SIGTERM,
-            StopSignal::Kill => libc::SIGKILL,
-            StopSignal::Usr1 => libc::SIGUSR1,
-            StopSignal::Usr2 => libc::SIGUSR2,
-            StopSignal::Stop => libc::SIGSTOP,
-            StopSignal::Alarm => libc::SIGALRM,
+/// The health check of a process.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HealthCheck {
+    /// The probe to run.
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// The delay between two probes, in seconds.
+    #[serde(default = "defaults::health_interval")]
+    pub interval: f64,
+    /// The amount of time after which a probe that has not succeeded fails, in seconds.
+    #[serde(default = "defaults::health_timeout")]
+    pub timeout: f64,
+    /// The number of consecutive failed probes after which the process is unhealthy.
+    #[serde(default = "defaults::health_failures")]
+    pub failures: u32,
+    /// What to do once the process is unhealthy.
+    #[serde(default)]
+    pub action: HealthAction,
+}
+
+/// When to rotate a log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Rotation {
+    /// The size past which the file is rotated, in bytes.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Whether the file is rotated when the day changes.
+    #[serde(default)]
+    pub daily: bool,
+    /// The number of rotated files to keep.
+    #[serde(default = "defaults::backups")]
+    pub backups: usize,
+    /// Whether rotated files are compressed with gzip.
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            daily: false,
+            backups: defaults::backups(),
+            gzip: false,
         }
     }
 }
@@ -108,22 +153,57 @@ pub struct ProgramConfig {
     /// The expected exit code of the program.
     #[serde(default = "defaults::exit_code")]
     pub exit_code: HashSet<u32>,
-    /// The amount of time to wait before marking the process as "healthy".
+    /// The amount of time to wait before marking the process as "healthy", in seconds.
+    ///
+    /// A process that exits before that, and that `restart` would restart, has failed to start.
+    /// Defaults to one second, so that a process that keeps crashing on launch is given up on
+    /// after `retries` attempts. This is ignored when the process notifies its readiness, or when
+    /// a health check is configured, as the process is then healthy once its first probe
+    /// succeeds.
     #[serde(default = "defaults::healthy_uptime")]
     pub healthy_uptime: f64,
-    /// The nu  mber of times to retry starting the process.
+    /// The number of consecutive failed starts to retry before giving up on the process.
     #[serde(default = "defaults::retries")]
     pub retries: u32,
+    /// The delay before restarting the process after it exits, in seconds.
+    #[serde(default = "defaults::backoff_initial")]
+    pub backoff_initial: f64,
+    /// The maximum delay between two restarts, in seconds.
+    #[serde(default = "defaults::backoff_max")]
+    pub backoff_max: f64,
+    /// The factor by which the delay grows after each consecutive failed start.
+    #[serde(default = "defaults::backoff_multiplier")]
+    pub backoff_multiplier: f64,
+    /// The fraction by which the delay is randomly lengthened or shortened, between 0 and 1.
+    #[serde(default)]
+    pub backoff_jitter: f64,
     /// The signal to send to the process to stop it.
     #[serde(default)]
-    pub signal: StopSignal,
+    pub signal: Signal,
     /// The amount of time to wait before sending a `SIGKILL` signal to the process.
     #[serde(default = "defaults::exit_timeout")]
     pub exit_timeout: f64,
-    /// If set, the process's standard output will be redirected to this file.
+    /// The signals to send in turn to stop the process, before it is killed.
+    ///
+    /// When empty, `signal` is sent and the process is killed after `exit_timeout`.
+    #[serde(default)]
+    pub stop_sequence: Vec<StopStep>,
+    /// Whether the stop signal is sent to the whole process group of the process, rather than
+    /// to the process alone.
+    #[serde(default = "defaults::as_group")]
+    pub stop_as_group: bool,
+    /// Whether `SIGKILL` is sent to the whole process group of the process, rather than to the
+    /// process alone.
+    #[serde(default = "defaults::as_group")]
+    pub kill_as_group: bool,
+    /// The file to which the lines the process writes to its standard output are appended.
+    ///
+    /// Defaults to `<program>.stdout.log` in `log_dir`, if it is set.
     #[serde(default)]
     pub stdout: Option<PathBuf>,
-    /// If set, the process's standard error will be redirected to this file.
+    /// The file to which the lines the process writes to its standard error are appended.
+    ///
+    /// Defaults to `<program>.stderr.log` in `log_dir`, if it is set.
     #[serde(default)]
     pub stderr: Option<PathBuf>,
     /// If set, the process's standard input will be redirected from this file.
@@ -138,6 +218,88 @@ pub struct ProgramConfig {
     /// The mask to apply when launching the process.
     #[serde(default, deserialize_with = "deserialize_umask")]
     pub umask: Option<libc::mode_t>,
+    /// The programs that must be running before the process is started.
+    #[serde(default)]
+    pub depends_on: BTreeSet<String>,
+    /// Programs with a lower priority are started first and stopped last.
+    #[serde(default)]
+    pub priority: i32,
+    /// Whether the process reports its state through the socket given in `NOTIFY_SOCKET`, as
+    /// with `sd_notify`.
+    ///
+    /// The process is healthy once it sends `READY=1`.
+    #[serde(default)]
+    pub notify: bool,
+    /// The amount of time the process may go without sending a keepalive before it is considered
+    /// hung, in seconds.
+    ///
+    /// A keepalive is a `WATCHDOG=1` notification, or an update of `heartbeat_file`, so either
+    /// `notify` or `heartbeat_file` must be set. A hung process goes through its stop sequence,
+    /// and is restarted according to `restart`.
+    #[serde(default)]
+    pub watchdog_timeout: Option<f64>,
+    /// A file whose modification time the process updates to show that it is alive.
+    #[serde(default)]
+    pub heartbeat_file: Option<PathBuf>,
+    /// Periodically checks that the process is working, rather than only running.
+    #[serde(default)]
+    pub healthcheck: Option<HealthCheck>,
+}
+
+impl ProgramConfig {
+    /// Returns the `index`-th step to go through to stop the process.
+    ///
+    /// Past the last step, the process is killed.
+    pub fn stop_step(&self, index: usize) -> Option<StopStep> {
+        if self.stop_sequence.is_empty() {
+            (index == 0).then_some(StopStep {
+                signal: self.signal,
+                timeout: self.exit_timeout,
+            })
+        } else {
+            self.stop_sequence.get(index).copied()
+        }
+    }
+
+    /// Returns the file to which the lines written to `stream` are appended.
+    pub fn output_file(&self, stream: Stream) -> Option<&Path> {
+        match stream {
+            Stream::Stdout => self.stdout.as_deref(),
+            Stream::Stderr => self.stderr.as_deref(),
+        }
+    }
+
+    /// Returns whether `signal` must be sent to the whole process group of the process.
+    pub fn signals_group(&self, signal: Signal) -> bool {
+        match signal {
+            Signal::KILL => self.kill_as_group,
+            _ => self.stop_as_group,
+        }
+    }
+
+    /// Returns the durations set in the configuration, in seconds, along with their option.
+    fn durations(&self) -> Vec<(&'static str, f64)> {
+        let mut durations = vec![
+            ("healthy_uptime", self.healthy_uptime),
+            ("backoff_initial", self.backoff_initial),
+            ("backoff_max", self.backoff_max),
+            ("exit_timeout", self.exit_timeout),
+        ];
+        durations.extend(
+            self.stop_sequence
+                .iter()
+                .map(|step| ("stop_sequence", step.timeout)),
+        );
+        durations.extend(
+            self.watchdog_timeout
+                .map(|timeout| ("watchdog_timeout", timeout)),
+        );
+        if let Some(healthcheck) = &self.healthcheck {
+            durations.push(("healthcheck.interval", healthcheck.interval));
+            durations.push(("healthcheck.timeout", healthcheck.timeout));
+        }
+        durations
+    }
 }
 
 mod defaults {
@@ -148,7 +310,7 @@ mod defaults {
     }
 
     pub fn healthy_uptime() -> f64 {
-        0.0
+        1.0
     }
 
     pub fn exit_code() -> HashSet<u32> {
@@ -168,13 +330,62 @@ mod defaults {
     pub fn exit_timeout() -> f64 {
         10.0
     }
+
+    pub fn as_group() -> bool {
+        true
+    }
+
+    pub fn health_interval() -> f64 {
+        10.0
+    }
+
+    pub fn health_timeout() -> f64 {
+        5.0
+    }
+
+    pub fn health_failures() -> u32 {
+        3
+    }
+
+    pub fn backoff_initial() -> f64 {
+        1.0
+    }
+
+    pub fn backoff_max() -> f64 {
+        60.0
+    }
+
+    pub fn backoff_multiplier() -> f64 {
+        2.0
+    }
+
+    pub fn backups() -> usize {
+        5
+    }
 }
 
 /// Contains the configuration of the file.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// The path of the Unix socket on which commands are accepted.
+    ///
+    /// No control socket is created unless this is set. This is only read at startup.
+    #[serde(default)]
+    pub control_socket: Option<PathBuf>,
+    /// The directory in which the output of the programs is logged, unless they set `stdout` or
+    /// `stderr`.
+    ///
+    /// Unless this is set, the output of those programs is only kept in memory.
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    /// When to rotate the log of the supervisor and the output logs of the programs.
+    #[serde(default)]
+    pub log_rotation: Rotation,
     /// The programs to start.
     pub programs: BTreeMap<String, ProgramConfig>,
+    /// Named sets of programs, controlled and reloaded together.
+    #[serde(default)]
+    pub groups: BTreeMap<String, BTreeSet<String>>,
 }
 
 impl Config {
@@ -185,11 +396,143 @@ impl Config {
     /// This function panics if the file cannot be opened or parsed.
     pub fn parse(file: &Path) -> Result<Self, Box<dyn Error>> {
         let file = std::fs::File::open(file)?;
-        let config = serde_yaml::from_reader(file)?;
+        let mut config: Self = serde_yaml::from_reader(file)?;
+        config.validate()?;
+        config.resolve_log_files();
         Ok(config)
     }
 
+    /// Gives the programs that do not set `stdout` or `stderr` a log file in `log_dir`.
+    fn resolve_log_files(&mut self) {
+        let Some(dir) = &self.log_dir else {
+            return;
+        };
+        for (name, program) in &mut self.programs {
+            program
+                .stdout
+                .get_or_insert_with(|| dir.join(format!("{name}.stdout.log")));
+            program
+                .stderr
+                .get_or_insert_with(|| dir.join(format!("{name}.stderr.log")));
+        }
+    }
+
+    /// Checks that the configuration is consistent.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (group, programs) in &self.groups {
+            if let Some(program) = programs.iter().find(|p| !self.programs.contains_key(*p)) {
+                return Err(
+                    format!("group `{group}` refers to unknown program `{program}`").into(),
+                );
+            }
+        }
+
+        for (name, program) in &self.programs {
+            if let Some(dependency) = program
+                .depends_on
+                .iter()
+                .find(|p| !self.programs.contains_key(*p))
+            {
+                return Err(format!("`{name}` depends on unknown program `{dependency}`").into());
+            }
+            if let Some((option, _)) = program
+                .durations()
+                .into_iter()
+                .find(|(_, duration)| !(0.0..=MAX_DURATION).contains(duration))
+            {
+                return Err(format!(
+                    "`{name}` has an invalid `{option}`: it must be between 0 and {MAX_DURATION} \
+                     seconds"
+                )
+                .into());
+            }
+            if program.watchdog_timeout.is_some()
+                && !program.notify
+                && program.heartbeat_file.is_none()
+            {
+                return Err(format!(
+                    "`{name}` has a `watchdog_timeout` but no way to send keepalives: \
+                     set `notify` or `heartbeat_file`"
+                )
+                .into());
+            }
+        }
+
+        if let Err(cycle) = self.dependency_order() {
+            return Err(format!("dependency cycle: {}", cycle.join(" -> ")).into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the names of the programs, each after the programs it depends on, and otherwise
+    /// by ascending priority.
+    ///
+    /// The dependencies must have been validated.
+    pub fn start_order(&self) -> Vec<&str> {
+        self.dependency_order()
+            .expect("dependencies are validated when parsing")
+    }
+
+    /// Sorts the programs so that each comes after the programs it depends on, and otherwise by
+    /// ascending priority.
+    ///
+    /// Fails with the programs forming a cycle, if any.
+    fn dependency_order(&self) -> Result<Vec<&str>, Vec<&str>> {
+        /// How far the visit of a program has gone.
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            config: &'a Config,
+            name: &'a str,
+            marks: &mut BTreeMap<&'a str, Mark>,
+            path: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<(), Vec<&'a str>> {
+            match marks.get(name) {
+                Some(Mark::Done) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|p| *p == name).unwrap();
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(name);
+                    return Err(cycle);
+                }
+                None => (),
+            }
+
+            marks.insert(name, Mark::Visiting);
+            path.push(name);
+            for dependency in config.by_priority(&config.programs[name].depends_on) {
+                visit(config, dependency, marks, path, order)?;
+            }
+            path.pop();
+            marks.insert(name, Mark::Done);
+            order.push(name);
+            Ok(())
+        }
+
+        let mut marks = BTreeMap::new();
+        let mut order = Vec::new();
+        for name in self.by_priority(self.programs.keys()) {
+            visit(self, name, &mut marks, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Sorts program names by ascending priority, and then by name.
+    fn by_priority<'a>(&self, names: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+        let mut names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+        names.sort_by_key(|name| (self.programs[*name].priority, *name));
+        names
+    }
+
     /// Computes the difference between `old` and `self`.
+    ///
+    /// Groups come first, followed by programs by descending priority, which is the order in
+    /// which they are stopped.
     pub fn diff_since(&self, old: &Self) -> Vec<ConfigDiff> {
         let mut diffs = Vec::new();
 
@@ -208,14 +551,55 @@ impl Config {
         }
 
         // Programs that are in both `old` and `self` are compared.
-        for (name, program) in self.programs.iter() {
-            if let Some(old_program) = old.programs.get(name) {
-                if old_program != program {
-                    diffs.push(ConfigDiff::ModifiedProgram(name.clone(), program.clone()));
-                }
+        let mut modified: BTreeSet<&str> = self
+            .programs
+            .iter()
+            .filter(|(name, program)| old.programs.get(*name).is_some_and(|old| old != *program))
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        for (name, programs) in self.groups.iter() {
+            let Some(old_programs) = old.groups.get(name) else {
+                diffs.push(ConfigDiff::AddedGroup(name.clone()));
+                continue;
+            };
+
+            // Groups are reloaded as units: when their members or one of their programs change,
+            // every program that was already running is restarted.
+            if old_programs != programs || programs.iter().any(|p| modified.contains(p.as_str())) {
+                diffs.push(ConfigDiff::ModifiedGroup(name.clone()));
+                modified.extend(
+                    programs
+                        .iter()
+                        .filter(|p| old.programs.contains_key(*p))
+                        .map(String::as_str),
+                );
+            }
+        }
+
+        for name in old.groups.keys() {
+            if !self.groups.contains_key(name) {
+                diffs.push(ConfigDiff::RemovedGroup(name.clone()));
             }
         }
 
+        for name in modified {
+            diffs.push(ConfigDiff::ModifiedProgram(
+                name.to_owned(),
+                self.programs[name].clone(),
+            ));
+        }
+
+        diffs.sort_by_key(|diff| {
+            std::cmp::Reverse(match diff {
+                ConfigDiff::AddedProgram(_, program) | ConfigDiff::ModifiedProgram(_, program) => {
+                    program.priority
+                }
+                ConfigDiff::RemovedProgram(name) => old.programs[name].priority,
+                _ => i32::MAX,
+            })
+        });
+
         diffs
     }
 }
@@ -226,6 +610,275 @@ pub enum ConfigDiff {
     AddedProgram(String, ProgramConfig),
     /// A program has been removed.
     RemovedProgram(String),
-    /// A program has been modified.
+    /// A program has been modified, or belongs to a group that has been.
     ModifiedProgram(String, ProgramConfig),
+    /// A group has been added.
+    AddedGroup(String),
+    /// A group has been removed.
+    RemovedGroup(String),
+    /// The programs of a group, or one of them, have been modified.
+    ModifiedGroup(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    /// Describes a diff, as `<kind> <name>`.
+    fn describe(diffs: Vec<ConfigDiff>) -> Vec<String> {
+        diffs
+            .into_iter()
+            .map(|diff| match diff {
+                ConfigDiff::AddedProgram(name, _) => format!("+program {name}"),
+                ConfigDiff::RemovedProgram(name) => format!("-program {name}"),
+                ConfigDiff::ModifiedProgram(name, _) => format!("~program {name}"),
+                ConfigDiff::AddedGroup(name) => format!("+group {name}"),
+                ConfigDiff::RemovedGroup(name) => format!("-group {name}"),
+                ConfigDiff::ModifiedGroup(name) => format!("~group {name}"),
+            })
+            .collect()
+    }
+
+    const BASE: &str = "
+programs:
+  web: { command: /bin/web }
+  api: { command: /bin/api }
+  db: { command: /bin/db }
+groups:
+  front: [web, api]
+";
+
+    #[test]
+    fn no_changes() {
+        assert!(config(BASE).diff_since(&config(BASE)).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_groups() {
+        let new = config(
+            "
+programs:
+  web: { command: /bin/web }
+  api: { command: /bin/api }
+  db: { command: /bin/db }
+groups:
+  back: [db]
+",
+        );
+        assert_eq!(
+            describe(new.diff_since(&config(BASE))),
+            ["+group back", "-group front"]
+        );
+    }
+
+    #[test]
+    fn modified_program_restarts_its_group() {
+        let new = config(
+            "
+programs:
+  web: { command: /bin/web, args: [--fast] }
+  api: { command: /bin/api }
+  db: { command: /bin/db }
+groups:
+  front: [web, api]
+",
+        );
+        assert_eq!(
+            describe(new.diff_since(&config(BASE))),
+            ["~group front", "~program api", "~program web"]
+        );
+    }
+
+    #[test]
+    fn modified_program_outside_groups() {
+        let new = config(
+            "
+programs:
+  web: { command: /bin/web }
+  api: { command: /bin/api }
+  db: { command: /bin/db2 }
+groups:
+  front: [web, api]
+",
+        );
+        assert_eq!(describe(new.diff_since(&config(BASE))), ["~program db"]);
+    }
+
+    #[test]
+    fn changed_members_restart_the_group() {
+        let new = config(
+            "
+programs:
+  web: { command: /bin/web }
+  api: { command: /bin/api }
+  db: { command: /bin/db }
+  cache: { command: /bin/cache }
+groups:
+  front: [web, api, cache]
+",
+        );
+        // The new member is only added, and the program left alone by the group is untouched.
+        assert_eq!(
+            describe(new.diff_since(&config(BASE))),
+            [
+                "~group front",
+                "+program cache",
+                "~program api",
+                "~program web"
+            ]
+        );
+    }
+
+    #[test]
+    fn programs_by_descending_priority() {
+        let old = config(
+            "
+programs:
+  low: { command: /bin/low, priority: 1 }
+  high: { command: /bin/high, priority: 10 }
+",
+        );
+        let new = config(
+            "
+programs:
+  low: { command: /bin/low2, priority: 1 }
+  mid: { command: /bin/mid, priority: 5 }
+",
+        );
+        assert_eq!(
+            describe(new.diff_since(&old)),
+            ["-program high", "+program mid", "~program low"]
+        );
+    }
+
+    #[test]
+    fn start_order_follows_dependencies_then_priority() {
+        let config = config(
+            "
+programs:
+  web: { command: /bin/web, depends_on: [api], priority: -10 }
+  api: { command: /bin/api, depends_on: [db] }
+  db: { command: /bin/db, priority: 5 }
+  cron: { command: /bin/cron, priority: 1 }
+  logs: { command: /bin/logs, priority: 1 }
+",
+        );
+        assert_eq!(config.start_order(), ["db", "api", "web", "cron", "logs"]);
+    }
+
+    fn validate(yaml: &str) -> Result<(), String> {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn dependency_cycles() {
+        assert_eq!(
+            validate(
+                "
+programs:
+  a: { command: /bin/a, depends_on: [b] }
+  b: { command: /bin/b, depends_on: [c] }
+  c: { command: /bin/c, depends_on: [a] }
+"
+            ),
+            Err("dependency cycle: a -> b -> c -> a".into())
+        );
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, depends_on: [a] }"),
+            Err("dependency cycle: a -> a".into())
+        );
+        // A diamond is not a cycle.
+        assert_eq!(
+            validate(
+                "
+programs:
+  a: { command: /bin/a, depends_on: [b, c] }
+  b: { command: /bin/b, depends_on: [d] }
+  c: { command: /bin/c, depends_on: [d] }
+  d: { command: /bin/d }
+"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn unknown_references() {
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, depends_on: [b] }"),
+            Err("`a` depends on unknown program `b`".into())
+        );
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a }\ngroups:\n  g: [a, b]"),
+            Err("group `g` refers to unknown program `b`".into())
+        );
+    }
+
+    #[test]
+    fn watchdog_needs_a_keepalive() {
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, watchdog_timeout: 5 }"),
+            Err(
+                "`a` has a `watchdog_timeout` but no way to send keepalives: \
+                 set `notify` or `heartbeat_file`"
+                    .into()
+            )
+        );
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, watchdog_timeout: 5, notify: true }"),
+            Ok(())
+        );
+        assert_eq!(
+            validate(
+                "programs:\n  a: { command: /bin/a, watchdog_timeout: 5, heartbeat_file: /tmp/a }"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn durations_in_range() {
+        let err = |option: &str| {
+            Err(format!(
+                "`a` has an invalid `{option}`: it must be between 0 and 31536000 seconds"
+            ))
+        };
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, healthy_uptime: 1e20 }"),
+            err("healthy_uptime")
+        );
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, backoff_initial: -1 }"),
+            err("backoff_initial")
+        );
+        assert_eq!(
+            validate("programs:\n  a: { command: /bin/a, exit_timeout: .nan }"),
+            err("exit_timeout")
+        );
+        assert_eq!(
+            validate(
+                "programs:\n  a: { command: /bin/a, stop_sequence: [{ signal: TERM, timeout: .inf }] }"
+            ),
+            err("stop_sequence")
+        );
+        assert_eq!(
+            validate(
+                "programs:\n  a: { command: /bin/a, healthcheck: { tcp: ':80', interval: 1e10 } }"
+            ),
+            err("healthcheck.interval")
+        );
+        assert_eq!(
+            validate(
+                "programs:\n  a: { command: /bin/a, healthy_uptime: 0, exit_timeout: 31536000 }"
+            ),
+            Ok(())
+        );
+    }
 }
@@ -0,0 +1,195 @@
+//! Accepts commands from other processes through a Unix-domain socket.
+
+use std::{
+    io::{BufReader, ErrorKind},
+    os::{
+        fd::AsFd,
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc, RwLock},
+};
+
+use taskmaster::protocol::{self, Reply};
+
+use crate::{
+    commands,
+    output::{self, FollowReceiver},
+    Taskmaster,
+};
+
+/// The control socket of the supervisor.
+///
+/// The socket file is removed when this value is dropped.
+#[derive(Debug)]
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+/// Binds a socket that only the owner of the supervisor can connect to, since anyone able to
+/// connect can start and stop programs.
+///
+/// The permissions are set through the umask, so that the socket is never accessible to others.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+impl ControlSocket {
+    /// Binds the control socket at `path`.
+    ///
+    /// A socket file left behind by a supervisor that did not exit cleanly is replaced, but
+    /// binding fails if another supervisor is still listening on it, or if the path is not a
+    /// socket.
+    ///
+    /// The socket is only accessible to the owner of the supervisor. This changes the umask of the
+    /// whole process for a moment, and must therefore be called before any thread is spawned.
+    pub fn bind(path: &Path) -> std::io::Result<Self> {
+        let listener = match bind_private(path) {
+            Ok(ok) => ok,
+            Err(err) if err.kind() == ErrorKind::AddrInUse => {
+                let is_socket = std::fs::symlink_metadata(path)?.file_type().is_socket();
+                if !is_socket || UnixStream::connect(path).is_ok() {
+                    return Err(err);
+                }
+                std::fs::remove_file(path)?;
+                bind_private(path)?
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            listener,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Starts accepting connections in a background thread.
+    pub fn serve(&self, taskmaster: Arc<RwLock<Taskmaster>>) -> std::io::Result<()> {
+        let listener = self.listener.try_clone()?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+
+                std::thread::spawn({
+                    let taskmaster = taskmaster.clone();
+                    move || handle_client(stream, &taskmaster)
+                });
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Answers the commands sent by a client until it disconnects.
+fn handle_client(stream: UnixStream, taskmaster: &RwLock<Taskmaster>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+
+    while let Ok(Some(line)) = protocol::read_request(&mut reader) {
+        let sent = match commands::execute(taskmaster, &line) {
+            (reply, None) => protocol::write_reply(&mut writer, &reply),
+            (reply, Some(follow)) => stream_reply(&mut reader, &mut writer, &reply, &follow),
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends the reply to a command followed by the lines written by the processes it follows, until
+/// the client sends a line or the processes are gone.
+fn stream_reply(
+    reader: &mut BufReader<UnixStream>,
+    writer: &mut UnixStream,
+    reply: &Reply,
+    follow: &FollowReceiver,
+) -> std::io::Result<()> {
+    protocol::write_partial(writer, &reply.lines)?;
+
+    // A line sent ahead of time stops the stream right away.
+    let stopped = !reader.buffer().is_empty()
+        || output::follow(
+            follow,
+            reader.get_ref().as_fd(),
+            &AtomicBool::new(false),
+            |lines| {
+                let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+                protocol::write_partial(writer, &lines)
+            },
+        )?;
+
+    let mut end = Reply::new();
+    if stopped {
+        if protocol::read_request(reader)?.is_none() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    } else {
+        end.push("The followed processes are gone");
+    }
+    protocol::write_reply(writer, &end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    /// Returns a path in a new empty directory for a test.
+    fn socket_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("taskmaster-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("taskmaster.sock")
+    }
+
+    #[test]
+    fn only_the_owner_can_connect() {
+        let path = socket_path("control-mode");
+        let socket = ControlSocket::bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn replaces_stale_sockets_only() {
+        let path = socket_path("control-stale");
+
+        // Left behind by a supervisor that did not exit cleanly.
+        drop(UnixListener::bind(&path).unwrap());
+        let socket = ControlSocket::bind(&path).unwrap();
+
+        let err = ControlSocket::bind(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        drop(socket);
+
+        std::fs::write(&path, "not a socket").unwrap();
+        let err = ControlSocket::bind(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Detaches the supervisor from its terminal and guards it with a pidfile.
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use libc::pid_t;
+
+/// An error that can occur while acquiring a pidfile.
+#[derive(Debug)]
+pub enum PidFileError {
+    /// Another instance is running and holds the lock on the pidfile.
+    AlreadyRunning(Option<pid_t>),
+    /// An unexpected I/O error occurred.
+    Io(std::io::Error),
+}
+
+impl Display for PidFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PidFileError::AlreadyRunning(Some(pid)) => {
+                write!(f, "another instance is already running (pid {pid})")
+            }
+            PidFileError::AlreadyRunning(None) => {
+                f.write_str("another instance is already running")
+            }
+            PidFileError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for PidFileError {
+    #[inline]
+    fn from(value: std::io::Error) -> Self {
+        PidFileError::Io(value)
+    }
+}
+
+/// A locked pidfile.
+///
+/// The lock is held for as long as the file stays open, which includes the copies of the file
+/// descriptor inherited through `fork`. The file is removed when this value is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Opens and locks the pidfile at `path`.
+    ///
+    /// If the file exists but is not locked, it was left behind by an instance that did not exit
+    /// cleanly. The PID it contains is returned alongside the pidfile so that it can be reported.
+    pub fn acquire(path: &Path) -> Result<(Self, Option<pid_t>), PidFileError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let previous_pid = contents.trim().parse::<pid_t>().ok();
+
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => Err(PidFileError::AlreadyRunning(previous_pid)),
+                _ => Err(err.into()),
+            };
+        }
+
+        let pidfile = Self {
+            file,
+            path: path.to_owned(),
+        };
+
+        Ok((pidfile, previous_pid))
+    }
+
+    /// Writes the PID of the current process to the pidfile.
+    pub fn write_pid(&mut self) -> std::io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", std::process::id())?;
+        self.file.sync_all()
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Forks, exiting the parent process.
+fn fork_and_exit_parent() -> std::io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Detaches the current process from its controlling terminal.
+///
+/// This must be called before any thread is spawned, as only the calling thread survives the
+/// forks. The working directory is left untouched so that relative paths in the configuration
+/// keep their meaning.
+pub fn daemonize() -> std::io::Result<()> {
+    fork_and_exit_parent()?;
+
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // Forking a second time ensures that the daemon is not a session leader and therefore
+    // can never acquire a controlling terminal again.
+    fork_and_exit_parent()?;
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("taskmaster-{test}-{}.pid", std::process::id()))
+    }
+
+    #[test]
+    fn acquire_fails_while_locked() {
+        let path = temp_path("pidfile-locked");
+        let _ = std::fs::remove_file(&path);
+
+        let (mut pidfile, previous_pid) = PidFile::acquire(&path).unwrap();
+        assert_eq!(previous_pid, None);
+        pidfile.write_pid().unwrap();
+
+        let pid = std::process::id() as pid_t;
+        match PidFile::acquire(&path) {
+            Err(PidFileError::AlreadyRunning(Some(running))) => assert_eq!(running, pid),
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        drop(pidfile);
+        assert!(!path.exists());
+        let (_pidfile, previous_pid) = PidFile::acquire(&path).unwrap();
+        assert_eq!(previous_pid, None);
+    }
+
+    #[test]
+    fn acquire_replaces_stale_pid() {
+        let path = temp_path("pidfile-stale");
+        std::fs::write(&path, "4242\n").unwrap();
+
+        let (mut pidfile, previous_pid) = PidFile::acquire(&path).unwrap();
+        assert_eq!(previous_pid, Some(4242));
+        pidfile.write_pid().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, format!("{}\n", std::process::id()));
+    }
+
+    #[test]
+    fn acquire_ignores_garbage() {
+        let path = temp_path("pidfile-garbage");
+        std::fs::write(&path, "not a pid\n").unwrap();
+
+        let (_pidfile, previous_pid) = PidFile::acquire(&path).unwrap();
+        assert_eq!(previous_pid, None);
+    }
+}
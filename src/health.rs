@@ -0,0 +1,252 @@
+//! The probes used to check that a process is working.
+//!
+//! Command probes are children of the supervisor, reaped by the reactor like the processes
+//! themselves. Network probes block, and are run by a small pool of threads shared by every
+//! process.
+
+use std::{
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use libc::pid_t;
+
+use crate::{
+    program::{unblock_signals, ProcessName},
+    reactor::Reactor,
+};
+
+/// The number of network probes that can run at once.
+const PROBE_WORKERS: usize = 4;
+
+/// The outcome of a probe, reported to the reactor.
+#[derive(Debug)]
+pub struct ProbeResult {
+    /// The name of the process that has been probed.
+    pub name: ProcessName,
+    /// The run of the process that has been probed.
+    pub run_id: u64,
+    /// Why the probe failed, if it did.
+    pub outcome: Result<(), String>,
+}
+
+/// What a network probe checks.
+#[derive(Debug)]
+pub enum Check {
+    /// A connection can be established to a TCP address.
+    Tcp(String),
+    /// A `GET` request to a URL succeeds.
+    Http(String),
+}
+
+/// A network probe of a run of a process.
+#[derive(Debug)]
+pub struct NetworkProbe {
+    /// The name of the process to probe.
+    pub name: ProcessName,
+    /// The run of the process to probe.
+    pub run_id: u64,
+    /// What to check.
+    pub check: Check,
+    /// The amount of time after which the probe fails.
+    pub timeout: Duration,
+}
+
+/// The threads running the network probes, which report their outcome to the reactor.
+#[derive(Debug)]
+pub struct ProbeWorkers {
+    sender: mpsc::Sender<NetworkProbe>,
+}
+
+impl ProbeWorkers {
+    /// Starts the threads.
+    pub fn spawn(reactor: Arc<Reactor>) -> Self {
+        let (sender, receiver) = mpsc::channel::<NetworkProbe>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        // A single client is shared, as each one starts a thread of its own.
+        let client = Arc::new(
+            reqwest::blocking::Client::builder()
+                .build()
+                .map_err(|err| format!("can't create HTTP client: {err}")),
+        );
+
+        for _ in 0..PROBE_WORKERS {
+            let receiver = receiver.clone();
+            let client = client.clone();
+            let reactor = reactor.clone();
+            std::thread::spawn(move || loop {
+                let Ok(probe) = receiver.lock().unwrap().recv() else {
+                    return;
+                };
+                let outcome = match &probe.check {
+                    Check::Tcp(address) => connect(address, probe.timeout),
+                    Check::Http(url) => match &*client {
+                        Ok(client) => get(client, url, probe.timeout),
+                        Err(err) => Err(err.clone()),
+                    },
+                };
+                reactor.complete_probe(ProbeResult {
+                    name: probe.name,
+                    run_id: probe.run_id,
+                    outcome,
+                });
+            });
+        }
+
+        Self { sender }
+    }
+
+    /// Runs a probe once a thread is available.
+    pub fn submit(&self, probe: NetworkProbe) {
+        let _ = self.sender.send(probe);
+    }
+}
+
+/// Spawns the command of a probe, without waiting for it.
+pub fn spawn_command(argv: &[String]) -> io::Result<pid_t> {
+    let Some((program, args)) = argv.split_first() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // A probe that times out is killed along with its descendants.
+    command.process_group(0);
+    unblock_signals(&mut command);
+
+    Ok(command.spawn()?.id() as pid_t)
+}
+
+/// Checks that a connection can be established to `address`.
+fn connect(address: &str, timeout: Duration) -> Result<(), String> {
+    let resolved = address
+        .to_socket_addrs()
+        .map_err(|err| format!("can't resolve {address}: {err}"))?
+        .next()
+        .ok_or_else(|| format!("can't resolve {address}"))?;
+    TcpStream::connect_timeout(&resolved, timeout)
+        .map_err(|err| format!("can't connect to {address}: {err}"))?;
+    Ok(())
+}
+
+/// Checks that a `GET` request to `url` succeeds.
+fn get(client: &reqwest::blocking::Client, url: &str, timeout: Duration) -> Result<(), String> {
+    let response = client.get(url).timeout(timeout).send().map_err(|err| {
+        if err.is_timeout() {
+            format!("GET {url}: timed out")
+        } else {
+            format!("GET {url}: {err}")
+        }
+    })?;
+    if !response.status().is_success() {
+        return Err(format!("GET {url}: {}", response.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Answers a single HTTP request with `status`, and returns the URL to request.
+    fn serve_once(status: &'static str) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                assert_ne!(len, 0);
+                request.extend_from_slice(&buf[..len]);
+            }
+            write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+        });
+        (url, server)
+    }
+
+    fn client() -> reqwest::blocking::Client {
+        reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+    }
+
+    /// Returns an address on which nothing listens.
+    fn closed_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn connect_succeeds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert_eq!(connect(&address, TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn connect_refused() {
+        let address = closed_address();
+        let err = connect(&address, TIMEOUT).unwrap_err();
+        assert!(
+            err.starts_with(&format!("can't connect to {address}")),
+            "{err}"
+        );
+        assert!(connect("no-port", TIMEOUT)
+            .unwrap_err()
+            .starts_with("can't resolve"));
+    }
+
+    #[test]
+    fn get_succeeds() {
+        let (url, server) = serve_once("204 No Content");
+        assert_eq!(get(&client(), &url, TIMEOUT), Ok(()));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_fails_on_error_status() {
+        let (url, server) = serve_once("503 Service Unavailable");
+        assert_eq!(
+            get(&client(), &url, TIMEOUT),
+            Err(format!("GET {url}: 503 Service Unavailable"))
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_refused() {
+        let url = format!("http://{}/", closed_address());
+        let err = get(&client(), &url, TIMEOUT).unwrap_err();
+        assert!(err.starts_with(&format!("GET {url}: ")), "{err}");
+    }
+
+    #[test]
+    fn get_times_out() {
+        // The connection is accepted by the kernel, but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        assert_eq!(
+            get(&client(), &url, Duration::from_millis(200)),
+            Err(format!("GET {url}: timed out"))
+        );
+    }
+}
@@ -0,0 +1,3 @@
+//! Code shared between the supervisor and its control client.
+
+pub mod protocol;
@@ -1,16 +1,18 @@
 use std::{
-    io::Write,
     sync::{Arc, RwLock},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    config::Rotation,
     program::{ExitCode, ProcessName},
+    rotation::LogFile,
+    signal::Signal,
     Taskmaster,
 };
 
-pub type LogSender = std::sync::mpsc::Sender<LogEvent>;
-pub type LogReceiver = std::sync::mpsc::Receiver<LogEvent>;
+pub type LogSender = std::sync::mpsc::Sender<LogMessage>;
+pub type LogReceiver = std::sync::mpsc::Receiver<LogMessage>;
 
 /// The kind of a log event.
 #[derive(Debug, Clone)]
@@ -23,8 +25,23 @@ pub enum LogEventKind {
     Failed(String),
     /// A process has exited.
     Exited(ExitCode),
+    /// A process will be restarted after the given delay.
+    Backoff(Duration),
+    /// A process did not stop in time, and has been sent the next signal of its stop sequence.
+    Escalated(Signal),
     /// A process has been killed.
     Killed,
+    /// A process has failed too many times and will not be restarted.
+    Fatal,
+    /// An orphaned descendant of a process has been adopted by the supervisor.
+    Adopted(libc::pid_t),
+    /// Too many consecutive health probes of a process have failed, the last one for the given
+    /// reason.
+    Unhealthy(String),
+    /// A health probe of an unhealthy process has succeeded.
+    Healthy,
+    /// A process has not sent a keepalive for the given amount of time, and is being stopped.
+    Hung(Duration),
 }
 
 /// An event that can be logged.
@@ -38,29 +55,82 @@ pub struct LogEvent {
     pub name: ProcessName,
 }
 
+/// A message received by the log thread.
+#[derive(Debug)]
+pub enum LogMessage {
+    /// An event concerning a process.
+    Event(LogEvent),
+    /// A message concerning the supervisor itself.
+    Notice(String),
+    /// Asks the log thread to acknowledge once every previous message has been written.
+    Flush(std::sync::mpsc::Sender<()>),
+    /// Changes when the log file is rotated.
+    SetRotation(Rotation),
+    /// Asks the log thread to reopen the log file, and to send back the errors that occur.
+    Reopen(std::sync::mpsc::Sender<Vec<String>>),
+}
+
+impl From<LogEvent> for LogMessage {
+    #[inline]
+    fn from(value: LogEvent) -> Self {
+        LogMessage::Event(value)
+    }
+}
+
+/// Blocks until every message sent through `sender` so far has been written.
+pub fn flush(sender: &LogSender) {
+    let (ack_sender, ack_receiver) = std::sync::mpsc::channel();
+    if sender.send(LogMessage::Flush(ack_sender)).is_ok() {
+        let _ = ack_receiver.recv();
+    }
+}
+
+/// Asks the log thread to reopen the log file, and waits for it to be done.
+///
+/// Returns the errors that occurred.
+pub fn reopen(sender: &LogSender) -> Vec<String> {
+    let (ack_sender, ack_receiver) = std::sync::mpsc::channel();
+    if sender.send(LogMessage::Reopen(ack_sender)).is_err() {
+        return Vec::new();
+    }
+    ack_receiver.recv().unwrap_or_default()
+}
+
 /// Gathers the logs and do stuff with them.
-pub fn gather_logs(
-    receiver: LogReceiver,
-    taskmaster: Arc<RwLock<Taskmaster>>,
-    mut file: std::fs::File,
-) {
+pub fn gather_logs(receiver: LogReceiver, taskmaster: Arc<RwLock<Taskmaster>>, mut file: LogFile) {
     let start_instant = Instant::now();
     let mut client = reqwest::blocking::Client::new();
-    while let Ok(ev) = receiver.recv() {
+    while let Ok(message) = receiver.recv() {
+        let ev = match message {
+            LogMessage::Event(ev) => ev,
+            LogMessage::Notice(notice) => {
+                let time = format_time(Instant::now().saturating_duration_since(start_instant));
+                special_print(&format!("{time}  {notice}\n"), &mut file, &mut client);
+                continue;
+            }
+            LogMessage::Flush(ack) => {
+                let _ = file.sync_data();
+                let _ = ack.send(());
+                continue;
+            }
+            LogMessage::SetRotation(rotation) => {
+                file.set_rotation(rotation);
+                continue;
+            }
+            LogMessage::Reopen(ack) => {
+                let errors = match file.reopen() {
+                    Ok(()) => Vec::new(),
+                    Err(err) => vec![format!("can't reopen `{}`: {err}", file.path().display())],
+                };
+                let _ = ack.send(errors);
+                continue;
+            }
+        };
+
         let since_start = ev.time.saturating_duration_since(start_instant);
 
-        let millis = since_start.subsec_millis();
-        let secs = since_start.as_secs();
-        let mins = secs / 60;
-        let hours = mins / 60;
         special_print(
-            &format!(
-                "{:02}:{:02}:{:02}.{:03}  ",
-                hours,
-                mins % 60,
-                secs % 60,
-                millis
-            ),
+            &format!("{}  ", format_time(since_start)),
             &mut file,
             &mut client,
         );
@@ -98,21 +168,75 @@ pub fn gather_logs(
 
                 special_print(&format!("exit code {}", status), &mut file, &mut client);
             }
+            LogEventKind::Backoff(delay) => {
+                special_print("\x1B[1;33mBACKOFF\x1B[0m   ", &mut file, &mut client);
+                special_print(
+                    &format!("restarting in {:.1}s", delay.as_secs_f64()),
+                    &mut file,
+                    &mut client,
+                );
+            }
+            LogEventKind::Escalated(signal) => {
+                special_print("\x1B[1;33mESCALATE\x1B[0m  ", &mut file, &mut client);
+                special_print(&format!("sending {signal}"), &mut file, &mut client);
+            }
             LogEventKind::Killed => {
                 special_print("\x1B[1;31mKILLED\x1B[0m    ", &mut file, &mut client);
             }
+            LogEventKind::Fatal => {
+                special_print("\x1B[1;31mFATAL\x1B[0m     ", &mut file, &mut client);
+                special_print("too many failed attempts", &mut file, &mut client);
+            }
+            LogEventKind::Adopted(pid) => {
+                special_print("\x1B[1;33mADOPTED\x1B[0m   ", &mut file, &mut client);
+                special_print(
+                    &format!("orphaned descendant (pid {pid})"),
+                    &mut file,
+                    &mut client,
+                );
+            }
+            LogEventKind::Unhealthy(reason) => {
+                special_print("\x1B[1;31mUNHEALTHY\x1B[0m ", &mut file, &mut client);
+                special_print(&reason, &mut file, &mut client);
+            }
+            LogEventKind::Healthy => {
+                special_print("\x1B[1;32mHEALTHY\x1B[0m   ", &mut file, &mut client);
+            }
+            LogEventKind::Hung(silence) => {
+                special_print("\x1B[1;31mHUNG\x1B[0m      ", &mut file, &mut client);
+                special_print(
+                    &format!("no keepalive for {:.1}s", silence.as_secs_f64()),
+                    &mut file,
+                    &mut client,
+                );
+            }
         }
 
         special_print("\n", &mut file, &mut client);
     }
 }
 
+/// Formats the time elapsed since the start of the supervisor.
+fn format_time(since_start: Duration) -> String {
+    let millis = since_start.subsec_millis();
+    let secs = since_start.as_secs();
+    let mins = secs / 60;
+    let hours = mins / 60;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        hours,
+        mins % 60,
+        secs % 60,
+        millis
+    )
+}
+
 /// Prints a string to the standard output and to a file.
-fn special_print(string: &str, file: &mut std::fs::File, client: &mut reqwest::blocking::Client) {
+fn special_print(string: &str, file: &mut LogFile, client: &mut reqwest::blocking::Client) {
     let _ = client
         .post(std::env::var("SERVER_URL").unwrap_or("http://localhost:8080/".into()))
         .body(string.to_owned())
         .send();
     print!("{}", string);
-    file.write_all(string.as_bytes()).unwrap();
+    let _ = file.write_all(string.as_bytes());
 }
@@ -1,25 +1,59 @@
+use args::{Action, Args};
 use config::Config;
-use logs::LogSender;
-use program::{Process, ProcessName};
+use logs::{LogMessage, LogSender};
+use output::OutputSender;
+use program::{Process, ProcessError, ProcessName, ProcessStatus};
+use reactor::Reactor;
+
+use taskmaster::protocol;
 
 use std::{
+    collections::BTreeSet,
     ffi::c_int,
+    fmt::Display,
+    os::fd::AsFd,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering::Relaxed},
+        mpsc::Sender,
         Arc, RwLock,
     },
+    time::Duration,
 };
 
+mod args;
 mod commands;
 mod config;
+mod control;
+mod daemon;
+mod health;
 mod logs;
+mod notify;
+mod output;
 mod program;
-
-const CONFIG_DEFAULT_PATH: &str = "config/run.yml";
-const LOG_DEFAULT_PATH: &str = "taskmaster.log";
+mod reactor;
+mod rotation;
+mod signal;
 
 fn main() -> std::process::ExitCode {
-    let config = match Config::parse(CONFIG_DEFAULT_PATH.as_ref()) {
+    let args = match Action::from_env() {
+        Ok(Action::Run(args)) => args,
+        Ok(Action::Help) => {
+            args::print_help();
+            return 0u8.into();
+        }
+        Ok(Action::Version) => {
+            args::print_version();
+            return 0u8.into();
+        }
+        Err(err) => {
+            eprintln!("\x1B[1;31merror\x1B[0m: {err}");
+            eprintln!("Try `--help` for more information.");
+            return 2u8.into();
+        }
+    };
+
+    let config = match Config::parse(&args.config) {
         Ok(ok) => ok,
         Err(err) => {
             eprintln!("\x1B[1;31merror\x1B[0m: can't parse config: {err}");
@@ -27,36 +61,206 @@ fn main() -> std::process::ExitCode {
         }
     };
 
+    let file = match rotation::LogFile::open(&args.log_file, config.log_rotation) {
+        Ok(ok) => ok,
+        Err(err) => {
+            eprintln!(
+                "\x1B[1;31merror\x1B[0m: can't open `{}`: {err}",
+                args.log_file.display()
+            );
+            return 2u8.into();
+        }
+    };
+
+    let mut pidfile = match args.pidfile.as_deref().map(daemon::PidFile::acquire) {
+        Some(Ok((pidfile, stale_pid))) => {
+            if let Some(pid) = stale_pid {
+                eprintln!("\x1B[1;33mwarning\x1B[0m: replacing stale pidfile (pid {pid})");
+            }
+            Some(pidfile)
+        }
+        Some(Err(err)) => {
+            eprintln!("\x1B[1;31merror\x1B[0m: can't acquire pidfile: {err}");
+            return 1u8.into();
+        }
+        None => None,
+    };
+
+    let control_socket = match &config.control_socket {
+        Some(path) => match control::ControlSocket::bind(path) {
+            Ok(ok) => Some(ok),
+            Err(err) => {
+                eprintln!(
+                    "\x1B[1;31merror\x1B[0m: can't bind control socket `{}`: {err}",
+                    path.display()
+                );
+                return 1u8.into();
+            }
+        },
+        None => None,
+    };
+
+    if args.daemon {
+        if let Err(err) = daemon::daemonize() {
+            eprintln!("\x1B[1;31merror\x1B[0m: can't daemonize: {err}");
+            return 1u8.into();
+        }
+    }
+
+    if let Some(pidfile) = &mut pidfile {
+        if let Err(err) = pidfile.write_pid() {
+            eprintln!("\x1B[1;31merror\x1B[0m: can't write pidfile: {err}");
+            return 1u8.into();
+        }
+    }
+
+    let Args {
+        config: config_path,
+        shell,
+        ..
+    } = args;
+
+    // This must happen before any thread is spawned.
+    let reactor = match Reactor::new() {
+        Ok(ok) => Arc::new(ok),
+        Err(err) => {
+            eprintln!("\x1B[1;31merror\x1B[0m: can't create the event loop: {err}");
+            return 1u8.into();
+        }
+    };
+
     let (log_sender, log_receiver) = std::sync::mpsc::channel();
-    let taskmaster = Arc::new(RwLock::new(Taskmaster::new(log_sender, config)));
+    let (output_sender, output_receiver) = std::sync::mpsc::channel();
+    let (shutdown_sender, shutdown_receiver) = std::sync::mpsc::channel();
 
-    let file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(LOG_DEFAULT_PATH)
-        .unwrap();
+    std::thread::spawn({
+        let log_sender = log_sender.clone();
+        let rotation = config.log_rotation;
+        move || output::write_output(output_receiver, log_sender, rotation)
+    });
+
+    let taskmaster = Arc::new(RwLock::new(Taskmaster::new(
+        log_sender,
+        output_sender,
+        shutdown_sender,
+        reactor.clone(),
+        config,
+        config_path,
+    )));
 
     std::thread::spawn({
         let taskmaster = taskmaster.clone();
         move || logs::gather_logs(log_receiver, taskmaster, file)
     });
 
-    run_shell(taskmaster);
+    std::thread::spawn({
+        let taskmaster = taskmaster.clone();
+        move || reactor.run(&taskmaster)
+    });
 
-    0u8.into()
+    if let Some(control_socket) = &control_socket {
+        if let Err(err) = control_socket.serve(taskmaster.clone()) {
+            eprintln!("\x1B[1;31merror\x1B[0m: can't serve control socket: {err}");
+            return 1u8.into();
+        }
+    }
+
+    if shell {
+        std::thread::spawn({
+            let taskmaster = taskmaster.clone();
+            move || run_shell(taskmaster)
+        });
+    }
+
+    // The `Taskmaster` instance holds a sender, so this never fails.
+    let reason = shutdown_receiver.recv().unwrap();
+
+    let log_sender = taskmaster.read().unwrap().log_sender.clone();
+    let _ = log_sender.send(LogMessage::Notice(format!("shutting down ({reason})")));
+    let summary = shutdown(&taskmaster);
+    let _ = log_sender.send(LogMessage::Notice(summary.to_string()));
+    logs::flush(&log_sender);
+
+    summary.exit_code().into()
+}
+
+/// The reason why the supervisor is shutting down.
+#[derive(Debug, Clone, Copy)]
+pub enum ShutdownReason {
+    /// The `shutdown` command was issued.
+    Command,
+    /// The standard input of the shell has been closed.
+    EndOfInput,
+    /// A termination signal was received.
+    Signal(c_int),
+}
+
+impl Display for ShutdownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownReason::Command => f.write_str("shutdown requested"),
+            ShutdownReason::EndOfInput => f.write_str("end of input"),
+            ShutdownReason::Signal(signal) => write!(f, "received {}", signal::Signal(*signal)),
+        }
+    }
+}
+
+/// What happened to the processes when the supervisor shut down.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShutdownSummary {
+    /// The number of processes that exited after receiving their stop signal.
+    pub stopped: usize,
+    /// The number of processes that had to be killed after their exit timeout.
+    pub killed: usize,
+}
+
+impl ShutdownSummary {
+    /// Returns the exit code of the supervisor.
+    ///
+    /// This is `0` if every process stopped gracefully, and `1` if some had to be killed.
+    pub fn exit_code(&self) -> u8 {
+        if self.killed == 0 {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl Display for ShutdownSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "shutdown complete: {} process(es) stopped, {} killed",
+            self.stopped, self.killed
+        )
+    }
 }
 
 /// Contains the state of the program.
 pub struct Taskmaster {
     log_sender: LogSender,
+    /// Sends the output captured from the processes to the output thread.
+    output_sender: OutputSender,
+    /// Used to ask the main thread to shut the supervisor down.
+    shutdown_sender: Sender<ShutdownReason>,
+    reactor: Arc<Reactor>,
     config: Config,
+    /// The path from which `config` was loaded, used when reloading.
+    config_path: PathBuf,
     processes: Vec<Process>,
 }
 
 impl Taskmaster {
     /// Creates a new [`Taskmaster`] instance.
-    pub fn new(log_sender: LogSender, config: Config) -> Self {
+    pub fn new(
+        log_sender: LogSender,
+        output_sender: OutputSender,
+        shutdown_sender: Sender<ShutdownReason>,
+        reactor: Arc<Reactor>,
+        config: Config,
+        config_path: PathBuf,
+    ) -> Self {
         let mut processes = Vec::new();
 
         for (name, config) in config.programs.iter() {
@@ -66,15 +270,36 @@ impl Taskmaster {
                     index: replica_index,
                 };
 
-                processes.push(Process::new(log_sender.clone(), name, config.clone()));
+                let process = Process::new(
+                    log_sender.clone(),
+                    output_sender.clone(),
+                    reactor.clone(),
+                    name,
+                    config.clone(),
+                );
+                processes.push(process);
             }
         }
 
-        Self {
+        let taskmaster = Self {
             log_sender,
+            output_sender,
+            shutdown_sender,
+            reactor,
             processes,
             config,
+            config_path,
+        };
+
+        for name in taskmaster.config.start_order() {
+            if taskmaster.config.programs[name].at_launch {
+                for process in taskmaster.get_processes_by_name(name) {
+                    let _ = taskmaster.launch(process);
+                }
+            }
         }
+
+        taskmaster
     }
 
     /// Gets a process by its name.
@@ -83,7 +308,7 @@ impl Taskmaster {
         self.processes.iter().find(|p| p.name() == name)
     }
 
-    /// Returns an iterator over all the processes.
+    /// Returns the replicas of a program.
     pub fn get_processes_by_name<'a>(
         &'a self,
         name: &'a str,
@@ -92,61 +317,267 @@ impl Taskmaster {
             .iter()
             .filter(move |p| p.name().name.as_ref() == name)
     }
-}
 
-fn split_whitespace(s: &str) -> (&str, &str) {
-    let index = s.find(char::is_whitespace).unwrap_or(s.len());
-    s.split_at(index)
-}
+    /// Returns whether every replica of the given programs is running.
+    fn dependencies_running(&self, programs: &BTreeSet<String>) -> bool {
+        self.processes
+            .iter()
+            .filter(|p| programs.contains(&*p.name().name))
+            .all(|p| p.status() == ProcessStatus::Running)
+    }
+
+    /// Requests a process to start, along with the programs it depends on.
+    ///
+    /// A process whose dependencies are not all running yet waits for them, and is started by the
+    /// reactor once they are.
+    pub fn launch(&self, process: &Process) -> Result<(), ProcessError> {
+        let depends_on = process.config().read().unwrap().depends_on.clone();
 
-fn handle_commands(taskmaster: &RwLock<Taskmaster>, mut line: &str) {
-    let command;
-    (command, line) = split_whitespace(line);
-    line = line.trim();
+        for dependency in &self.processes {
+            if depends_on.contains(&*dependency.name().name)
+                && matches!(
+                    dependency.status(),
+                    ProcessStatus::Stopped | ProcessStatus::Exited | ProcessStatus::Fatal
+                )
+            {
+                let _ = self.launch(dependency);
+            }
+        }
+
+        if self.dependencies_running(&depends_on) {
+            return process.launch();
+        }
 
-    match command.trim() {
-        "start" => commands::start(line, &taskmaster.read().unwrap()),
-        "stop" => commands::stop(line, &taskmaster.read().unwrap()),
-        "restart" => commands::restart(line, &taskmaster.read().unwrap()),
-        "status" => commands::status(line, &taskmaster.read().unwrap()),
-        "reload" => commands::reload(line, &mut taskmaster.write().unwrap()),
-        _ => println!("Unknown command: {}", command),
+        process.wait_for_dependencies()?;
+        // The reactor does not start a process whose dependencies became running before it was
+        // waiting for them.
+        if self.dependencies_running(&depends_on) {
+            process.start_waiting();
+        }
+        Ok(())
     }
-}
 
-static HANGED: AtomicBool = AtomicBool::new(false);
+    /// Starts the waiting processes whose dependencies are now running.
+    pub fn start_waiting_processes(&self) {
+        for process in &self.processes {
+            if process.status() != ProcessStatus::Waiting {
+                continue;
+            }
+            let depends_on = process.config().read().unwrap().depends_on.clone();
+            if self.dependencies_running(&depends_on) {
+                process.start_waiting();
+            }
+        }
+    }
 
-unsafe extern "C" fn hangup_handler(_signal: c_int) {
-    HANGED.store(true, Relaxed);
-}
+    /// Returns whether a process that must stop before `process` is still alive.
+    ///
+    /// Those are the processes of the programs in `before` that depend on it, or that have a
+    /// higher priority.
+    fn must_wait_to_stop(&self, process: &Process, before: &[String]) -> bool {
+        let priority = process.config().read().unwrap().priority;
+        self.processes.iter().any(|p| {
+            let config = p.config().read().unwrap();
+            before.iter().any(|name| **name == *p.name().name)
+                && (config.depends_on.contains(&*process.name().name) || config.priority > priority)
+                && p.is_alive()
+        })
+    }
 
-fn check_hangup(taskmaster: Arc<RwLock<Taskmaster>>) {
-    loop {
-        if HANGED.swap(false, Relaxed) {
-            println!("Hangup received, reloading config");
-            commands::reload("", &mut taskmaster.write().unwrap());
+    /// Returns the processes designated by the whitespace-separated `targets`, in order and
+    /// without duplicates.
+    ///
+    /// A target is `all`, a group as in `group:backend`, or a pattern as accepted by
+    /// [`ProcessName::matches`]. The first target that designates no process is returned as an
+    /// error.
+    pub fn get_processes_by_targets<'a, 't>(
+        &'a self,
+        targets: &'t str,
+    ) -> Result<Vec<&'a Process>, &'t str> {
+        let mut processes: Vec<&Process> = Vec::new();
+
+        for target in targets.split_whitespace() {
+            let group = target
+                .strip_prefix("group:")
+                .map(|group| self.config.groups.get(group));
+
+            let mut found = false;
+            for process in &self.processes {
+                let is_target = match group {
+                    Some(programs) => programs.is_some_and(|p| p.contains(&*process.name().name)),
+                    None => target == "all" || process.name().matches(target),
+                };
+                if is_target {
+                    found = true;
+                    if !processes.iter().any(|p| std::ptr::eq(*p, process)) {
+                        processes.push(process);
+                    }
+                }
+            }
+            if !found {
+                return Err(target);
+            }
         }
 
-        std::hint::spin_loop();
+        Ok(processes)
+    }
+
+    /// Asks the main thread to shut the supervisor down.
+    pub fn request_shutdown(&self, reason: ShutdownReason) {
+        let _ = self.shutdown_sender.send(reason);
     }
 }
 
-/// Runs the shell.
-fn run_shell(taskmaster: Arc<RwLock<Taskmaster>>) {
-    unsafe {
-        libc::signal(libc::SIGHUP, hangup_handler as usize);
+/// Stops every process and waits for all of them to exit.
+///
+/// The processes are removed once they have all exited.
+fn shutdown(taskmaster: &RwLock<Taskmaster>) -> ShutdownSummary {
+    let programs = taskmaster
+        .read()
+        .unwrap()
+        .config
+        .programs
+        .keys()
+        .cloned()
+        .collect();
+    let summary = stop_programs(taskmaster, &programs);
+
+    taskmaster.write().unwrap().processes.clear();
+    summary
+}
+
+/// Stops the processes of `programs`, and waits for all of them to exit.
+///
+/// Each running process goes through its stop sequence, driven by the reactor, and is killed if it
+/// is still running after the last step. A process is only asked to stop once the processes that
+/// depend on it, and those with a higher priority, have exited.
+///
+/// The lock is only held briefly, as the reactor needs it to notice that the processes exit.
+pub fn stop_programs(
+    taskmaster: &RwLock<Taskmaster>,
+    programs: &BTreeSet<String>,
+) -> ShutdownSummary {
+    let mut summary = ShutdownSummary::default();
+
+    let (stop_order, mut remaining) = {
+        let taskmaster = taskmaster.read().unwrap();
+
+        // Programs are stopped in the reverse order in which they are started.
+        let stop_order: Vec<String> = taskmaster
+            .config
+            .start_order()
+            .into_iter()
+            .rev()
+            .filter(|name| programs.contains(*name))
+            .map(str::to_owned)
+            .collect();
+
+        // The processes that have not been asked to stop yet, in stop order.
+        let remaining: Vec<ProcessName> = stop_order
+            .iter()
+            .flat_map(|name| taskmaster.get_processes_by_name(name))
+            .map(|p| p.name().clone())
+            .collect();
+
+        (stop_order, remaining)
+    };
+
+    // The processes that have been asked to stop.
+    let mut pending = Vec::new();
+
+    while !remaining.is_empty() || !pending.is_empty() {
+        let taskmaster = taskmaster.read().unwrap();
+
+        remaining.retain(|name| {
+            let Some(process) = taskmaster.get_process_by_process_name(name) else {
+                return false;
+            };
+            let rank = stop_order.iter().position(|p| **p == *name.name);
+            let before = &stop_order[..rank.unwrap_or(0)];
+            if taskmaster.must_wait_to_stop(process, before) {
+                return true;
+            }
+            if process.request_stop().is_ok() {
+                pending.push(name.clone());
+            }
+            false
+        });
+
+        pending.retain(|name| match taskmaster.get_process_by_process_name(name) {
+            Some(process) if process.is_alive() => true,
+            process => {
+                if process.is_some_and(|p| p.was_killed()) {
+                    summary.killed += 1;
+                } else {
+                    summary.stopped += 1;
+                }
+                false
+            }
+        });
+        drop(taskmaster);
+
+        std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
     }
 
-    std::thread::spawn({
-        let taskmaster = taskmaster.clone();
-        move || check_hangup(taskmaster)
-    });
+    summary
+}
+
+/// How often the processes are checked while waiting for them to exit during shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
+/// Runs the shell.
+fn run_shell(taskmaster: Arc<RwLock<Taskmaster>>) {
+    let reactor = taskmaster.read().unwrap().reactor.clone();
     let mut readline = ft::readline::Readline::new();
 
     while readline.read().unwrap() {
         readline.history_add_buffer().unwrap();
         println!();
-        handle_commands(&taskmaster, readline.buffer());
+
+        let (reply, follow) = commands::execute(&taskmaster, readline.buffer());
+        print_reply(&reply);
+        if let Some(follow) = follow {
+            follow_output(&reactor, &follow);
+        }
+    }
+
+    taskmaster
+        .read()
+        .unwrap()
+        .request_shutdown(ShutdownReason::EndOfInput);
+}
+
+/// Prints the lines written by followed processes until the shell is interrupted or a line is
+/// entered.
+///
+/// Meanwhile, an interrupt only stops following, rather than shutting the supervisor down.
+fn follow_output(reactor: &Reactor, follow: &output::FollowReceiver) {
+    println!("(press Ctrl-C or Enter to stop following)");
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    reactor.set_follow_interrupt(Some(interrupted.clone()));
+    let stdin = std::io::stdin();
+    let stopped = output::follow(follow, stdin.as_fd(), &interrupted, |lines| {
+        for line in lines {
+            println!("{line}");
+        }
+        Ok(())
+    });
+    reactor.set_follow_interrupt(None);
+
+    match stopped {
+        Ok(true) if interrupted.load(Relaxed) => println!(),
+        Ok(true) => {
+            let _ = stdin.read_line(&mut String::new());
+        }
+        Ok(false) => println!("The followed processes are gone"),
+        Err(err) => eprintln!("\x1B[1;31merror\x1B[0m: can't follow the output: {err}"),
+    }
+}
+
+/// Prints the reply to a command on the standard output.
+fn print_reply(reply: &protocol::Reply) {
+    for line in &reply.lines {
+        println!("{line}");
     }
 }
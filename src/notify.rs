@@ -0,0 +1,264 @@
+//! The `sd_notify` protocol, through which a process reports its own state.
+//!
+//! Each process that uses it is given a datagram socket in the abstract namespace, whose address
+//! is exported in `NOTIFY_SOCKET`. Datagrams hold newline-separated `KEY=VALUE` assignments.
+//!
+//! Since anyone can send to an abstract socket, each datagram comes with the credentials of its
+//! sender, which the kernel fills in, so that those not sent by the process can be dropped.
+
+use std::{
+    io, mem,
+    os::{
+        fd::AsRawFd,
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+};
+
+use libc::pid_t;
+
+use crate::{
+    program::ProcessName,
+    reactor::{Reactor, Registration, Source},
+};
+
+/// Distinguishes the sockets created by the supervisor.
+static NEXT_SOCKET_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A notification sent by a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// `READY=1`: the process has finished starting up.
+    Ready,
+    /// `STATUS=...`: a description of what the process is doing.
+    Status(String),
+    /// `WATCHDOG=1`: the process is still alive.
+    Watchdog,
+    /// `STOPPING=1`: the process is shutting down.
+    Stopping,
+    /// `MAINPID=...`: the main process is now another one.
+    MainPid(pid_t),
+}
+
+impl Notification {
+    /// Parses a single assignment, ignoring those that are not understood.
+    fn parse(assignment: &str) -> Option<Self> {
+        let (key, value) = assignment.split_once('=')?;
+        match (key, value) {
+            ("READY", "1") => Some(Self::Ready),
+            ("STATUS", _) => Some(Self::Status(value.to_owned())),
+            ("WATCHDOG", "1") => Some(Self::Watchdog),
+            ("STOPPING", "1") => Some(Self::Stopping),
+            ("MAINPID", _) => value.parse().ok().filter(|&pid| pid > 0).map(Self::MainPid),
+            _ => None,
+        }
+    }
+
+    /// Parses the newline-separated assignments of a datagram.
+    fn parse_datagram(datagram: &[u8]) -> Vec<Self> {
+        let datagram = String::from_utf8_lossy(datagram);
+        datagram.lines().filter_map(Self::parse).collect()
+    }
+}
+
+/// The socket on which a process sends its notifications.
+///
+/// It is watched by the reactor as long as it exists.
+#[derive(Debug)]
+pub struct NotifySocket {
+    /// Declared first, so that the socket is no longer watched once it is closed.
+    _registration: Registration,
+    socket: UnixDatagram,
+    /// The value of `NOTIFY_SOCKET`.
+    address: String,
+}
+
+impl NotifySocket {
+    /// Creates the notification socket of a process.
+    pub fn bind(reactor: &Arc<Reactor>, name: &ProcessName) -> io::Result<Self> {
+        let id = NEXT_SOCKET_ID.fetch_add(1, Relaxed);
+        let abstract_name = format!("taskmaster/{}/{id}", std::process::id());
+
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&abstract_name)?)?;
+        socket.set_nonblocking(true)?;
+        let enable: libc::c_int = 1;
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                (&enable as *const libc::c_int).cast(),
+                mem::size_of_val(&enable) as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let registration = reactor.watch(socket.as_raw_fd(), name.clone(), Source::Notify)?;
+
+        Ok(Self {
+            _registration: registration,
+            socket,
+            address: format!("@{abstract_name}"),
+        })
+    }
+
+    /// Returns the address of the socket, as exported in `NOTIFY_SOCKET`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Reads the notifications that have been received, along with the pid of their sender.
+    pub fn receive(&self) -> Vec<(pid_t, Notification)> {
+        let mut notifications = Vec::new();
+        let mut buf = [0u8; 4096];
+        while let Ok((len, sender)) = self.recv_with_sender(&mut buf) {
+            let Some(sender) = sender else {
+                continue;
+            };
+            let parsed = Notification::parse_datagram(&buf[..len]);
+            notifications.extend(
+                parsed
+                    .into_iter()
+                    .map(|notification| (sender, notification)),
+            );
+        }
+        notifications
+    }
+
+    /// Reads a datagram, and the pid of its sender from its `SCM_CREDENTIALS`.
+    ///
+    /// File descriptors passed along with the datagram are closed.
+    fn recv_with_sender(&self, buf: &mut [u8]) -> io::Result<(usize, Option<pid_t>)> {
+        // Room for the credentials, and for a few file descriptors that are then closed.
+        let mut control = [0u64; 16];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control);
+
+        let flags = libc::MSG_CMSG_CLOEXEC | libc::MSG_DONTWAIT;
+        let len = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, flags) };
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut sender = None;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            let data_len = header.cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize;
+            match (header.cmsg_level, header.cmsg_type) {
+                (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => {
+                    let credentials: libc::ucred =
+                        unsafe { data.cast::<libc::ucred>().read_unaligned() };
+                    sender = Some(credentials.pid);
+                }
+                (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                    for i in 0..data_len / mem::size_of::<libc::c_int>() {
+                        let fd = unsafe { data.cast::<libc::c_int>().add(i).read_unaligned() };
+                        unsafe { libc::close(fd) };
+                    }
+                }
+                _ => {}
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        Ok((len as usize, sender))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_assignments() {
+        assert_eq!(Notification::parse("READY=1"), Some(Notification::Ready));
+        assert_eq!(
+            Notification::parse("WATCHDOG=1"),
+            Some(Notification::Watchdog)
+        );
+        assert_eq!(
+            Notification::parse("STOPPING=1"),
+            Some(Notification::Stopping)
+        );
+        assert_eq!(
+            Notification::parse("STATUS=Serving 3 clients = ok"),
+            Some(Notification::Status("Serving 3 clients = ok".into()))
+        );
+        assert_eq!(
+            Notification::parse("STATUS="),
+            Some(Notification::Status(String::new()))
+        );
+        assert_eq!(
+            Notification::parse("MAINPID=4242"),
+            Some(Notification::MainPid(4242))
+        );
+    }
+
+    #[test]
+    fn parse_ignores_what_is_not_understood() {
+        assert_eq!(Notification::parse("READY=0"), None);
+        assert_eq!(Notification::parse("READY"), None);
+        assert_eq!(Notification::parse("ready=1"), None);
+        assert_eq!(Notification::parse("RELOADING=1"), None);
+        assert_eq!(Notification::parse("MAINPID=0"), None);
+        assert_eq!(Notification::parse("MAINPID=-1"), None);
+        assert_eq!(Notification::parse("MAINPID=abc"), None);
+        assert_eq!(Notification::parse(""), None);
+    }
+
+    #[test]
+    fn parse_datagrams() {
+        assert_eq!(
+            Notification::parse_datagram(b"READY=1\nSTATUS=up\nERRNO=2\n\nMAINPID=7"),
+            [
+                Notification::Ready,
+                Notification::Status("up".into()),
+                Notification::MainPid(7),
+            ]
+        );
+        assert_eq!(Notification::parse_datagram(b""), []);
+    }
+
+    #[test]
+    fn receive_with_sender() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let name = ProcessName {
+            name: Arc::from("web"),
+            index: 0,
+        };
+        let socket = NotifySocket::bind(&reactor, &name).unwrap();
+        let address = socket.address().strip_prefix('@').unwrap();
+        let address = SocketAddr::from_abstract_name(address).unwrap();
+
+        let client = UnixDatagram::unbound().unwrap();
+        client
+            .send_to_addr(b"READY=1\nSTATUS=up", &address)
+            .unwrap();
+        client.send_to_addr(b"WATCHDOG=1", &address).unwrap();
+
+        let pid = std::process::id() as pid_t;
+        assert_eq!(
+            socket.receive(),
+            [
+                (pid, Notification::Ready),
+                (pid, Notification::Status("up".into())),
+                (pid, Notification::Watchdog),
+            ]
+        );
+        assert_eq!(socket.receive(), []);
+    }
+}
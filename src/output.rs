@@ -0,0 +1,508 @@
+//! Captures the output of the processes.
+//!
+//! The standard output and error of every process are pipes read by the reactor. Each line is
+//! timestamped and kept in memory by the process, and sent to the output thread, which appends it
+//! to the log file of its program. The lines can also be followed live, as they are read.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+    os::fd::{AsRawFd, BorrowedFd, OwnedFd},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        mpsc, Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::Rotation,
+    logs::{LogMessage, LogSender},
+    program::ProcessName,
+    reactor::{Reactor, Registration, Source},
+    rotation::LogFile,
+};
+
+/// The number of lines of each stream kept in memory for every process.
+pub const BUFFERED_LINES: usize = 1000;
+
+/// Lines longer than this are split.
+const MAX_LINE_LEN: usize = 16 * 1024;
+
+/// How many reads of a pipe are done each time it is readable, so that a process that writes
+/// without pause does not hold up the reactor. The pipes are watched level-triggered, so the rest
+/// is read on the next wakeup.
+const READS_PER_WAKEUP: usize = 4;
+
+/// How often a follower checks whether it should stop while no line is written.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub type OutputSender = mpsc::Sender<OutputMessage>;
+pub type OutputReceiver = mpsc::Receiver<OutputMessage>;
+
+/// Receives the lines written by followed processes.
+pub type FollowReceiver = mpsc::Receiver<OutputLine>;
+
+/// An output stream of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// The standard output.
+    Stdout,
+    /// The standard error.
+    Stderr,
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Stdout => f.pad("stdout"),
+            Stream::Stderr => f.pad("stderr"),
+        }
+    }
+}
+
+/// A line written by a process.
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    /// The time at which the line was read.
+    pub time: SystemTime,
+    /// The process that wrote the line.
+    pub name: ProcessName,
+    /// The line, without its terminating newline.
+    pub text: String,
+}
+
+impl Display for OutputLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            format_timestamp(self.time),
+            self.name,
+            self.text
+        )
+    }
+}
+
+/// Breaks a time down in the local time zone.
+pub fn local_time(time: SystemTime) -> libc::tm {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs() as libc::time_t;
+
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    tm
+}
+
+/// Formats a time as a local date and time, with milliseconds.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let tm = local_time(time);
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_millis();
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        millis,
+    )
+}
+
+/// A message received by the output thread.
+#[derive(Debug)]
+pub enum OutputMessage {
+    /// A line to append to a log file.
+    Line { path: PathBuf, line: OutputLine },
+    /// Changes when the log files are rotated.
+    SetRotation(Rotation),
+    /// Asks the output thread to reopen the log files, and to send back the errors that occur.
+    Reopen(mpsc::Sender<Vec<String>>),
+}
+
+/// A pipe from which the output of a process is read.
+#[derive(Debug)]
+struct OutputPipe {
+    /// Declared first, so that the pipe is no longer watched once it is closed.
+    registration: Registration,
+    file: File,
+    stream: Stream,
+    /// The beginning of a line whose end has not been read yet.
+    partial: Vec<u8>,
+}
+
+impl OutputPipe {
+    /// Reads some of what is available in the pipe, at most [`READS_PER_WAKEUP`] times, and
+    /// returns the complete lines.
+    ///
+    /// Returns whether the pipe has been closed by every writer as well.
+    fn read_lines(&mut self) -> (Vec<String>, bool) {
+        let mut lines = Vec::new();
+        let mut buf = [0u8; 4096];
+        let mut reads = 0;
+
+        let closed = loop {
+            if reads == READS_PER_WAKEUP {
+                break false;
+            }
+            match self.file.read(&mut buf) {
+                Ok(0) => break true,
+                Ok(len) => self.partial.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => break err.kind() != io::ErrorKind::WouldBlock,
+            }
+            reads += 1;
+
+            while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.partial.drain(..=end).collect();
+                lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+            if self.partial.len() >= MAX_LINE_LEN {
+                lines.push(String::from_utf8_lossy(&self.partial).into_owned());
+                self.partial.clear();
+            }
+        };
+
+        if closed && !self.partial.is_empty() {
+            lines.push(String::from_utf8_lossy(&self.partial).into_owned());
+            self.partial.clear();
+        }
+        (lines, closed)
+    }
+}
+
+/// The captured output of a process.
+#[derive(Debug, Default)]
+pub struct Output {
+    /// The pipes that are still open, which may outlive the run that created them.
+    pipes: Vec<OutputPipe>,
+    /// The last lines written to the standard output.
+    stdout: VecDeque<OutputLine>,
+    /// The last lines written to the standard error.
+    stderr: VecDeque<OutputLine>,
+    /// The streams being followed, and where to send their new lines.
+    followers: Vec<(Stream, mpsc::Sender<OutputLine>)>,
+}
+
+impl Output {
+    /// Starts capturing a stream of a process from the read end of its pipe.
+    pub fn capture(
+        &mut self,
+        reactor: &Arc<Reactor>,
+        name: &ProcessName,
+        stream: Stream,
+        fd: OwnedFd,
+    ) -> io::Result<()> {
+        let ret = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let registration = reactor.watch(fd.as_raw_fd(), name.clone(), Source::Output)?;
+        self.pipes.push(OutputPipe {
+            registration,
+            file: File::from(fd),
+            stream,
+            partial: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Returns the last lines written to a stream.
+    pub fn lines(&self, stream: Stream) -> &VecDeque<OutputLine> {
+        match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+        }
+    }
+
+    /// Sends the lines written to a stream from now on to `sender`, until its receiver is dropped.
+    pub fn follow(&mut self, stream: Stream, sender: mpsc::Sender<OutputLine>) {
+        self.followers.push((stream, sender));
+    }
+
+    /// Reads from the pipe identified by `token`, and keeps the lines that were written.
+    ///
+    /// The lines are returned along with their stream.
+    pub fn read(&mut self, token: u64, name: &ProcessName) -> Vec<(Stream, OutputLine)> {
+        let Some(index) = self
+            .pipes
+            .iter()
+            .position(|p| p.registration.token() == token)
+        else {
+            return Vec::new();
+        };
+
+        let pipe = &mut self.pipes[index];
+        let stream = pipe.stream;
+        let (texts, closed) = pipe.read_lines();
+        if closed {
+            self.pipes.swap_remove(index);
+        }
+
+        let time = SystemTime::now();
+        let buffer = match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        };
+
+        let mut lines = Vec::with_capacity(texts.len());
+        for text in texts {
+            let line = OutputLine {
+                time,
+                name: name.clone(),
+                text,
+            };
+            if buffer.len() == BUFFERED_LINES {
+                buffer.pop_front();
+            }
+            buffer.push_back(line.clone());
+            lines.push((stream, line));
+        }
+
+        self.followers.retain(|(followed, sender)| {
+            *followed != stream
+                || lines
+                    .iter()
+                    .all(|(_, line)| sender.send(line.clone()).is_ok())
+        });
+        lines
+    }
+}
+
+/// Passes the lines received from followed processes to `forward`, until `input` has something
+/// to read, `interrupted` is set, or every followed process is gone.
+///
+/// Returns whether following was stopped through `input` or `interrupted`.
+pub fn follow(
+    receiver: &FollowReceiver,
+    input: BorrowedFd,
+    interrupted: &AtomicBool,
+    mut forward: impl FnMut(Vec<OutputLine>) -> io::Result<()>,
+) -> io::Result<bool> {
+    loop {
+        match receiver.recv_timeout(FOLLOW_POLL_INTERVAL) {
+            Ok(line) => {
+                let mut lines = vec![line];
+                lines.extend(receiver.try_iter());
+                forward(lines)?;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(false),
+        }
+        if interrupted.load(Relaxed) {
+            return Ok(true);
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd: input.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => (),
+            _ => return Ok(true),
+        }
+    }
+}
+
+/// Asks the output thread to reopen the log files, and waits for it to be done.
+///
+/// Returns the errors that occurred.
+pub fn reopen(sender: &OutputSender) -> Vec<String> {
+    let (ack_sender, ack_receiver) = mpsc::channel();
+    if sender.send(OutputMessage::Reopen(ack_sender)).is_err() {
+        return Vec::new();
+    }
+    ack_receiver.recv().unwrap_or_default()
+}
+
+/// Writes the lines captured from the processes to their log files.
+///
+/// A log file that cannot be opened is reported once, and its lines are dropped until the log
+/// files are reopened.
+pub fn write_output(receiver: OutputReceiver, log_sender: LogSender, mut rotation: Rotation) {
+    let mut files: HashMap<PathBuf, Option<LogFile>> = HashMap::new();
+    let report = |message: String| {
+        let _ = log_sender.send(LogMessage::Notice(message));
+    };
+
+    while let Ok(message) = receiver.recv() {
+        match message {
+            OutputMessage::Line { path, line } => {
+                let file = files.entry(path).or_insert_with_key(|path| {
+                    LogFile::open(path, rotation)
+                        .map_err(|err| report(format!("can't open `{}`: {err}", path.display())))
+                        .ok()
+                });
+                let Some(file) = file else {
+                    continue;
+                };
+                if let Err(err) = file.write_all(format!("{line}\n").as_bytes()) {
+                    report(format!("can't write to `{}`: {err}", file.path().display()));
+                }
+            }
+            OutputMessage::SetRotation(new_rotation) => {
+                rotation = new_rotation;
+                for file in files.values_mut().flatten() {
+                    file.set_rotation(rotation);
+                }
+            }
+            OutputMessage::Reopen(ack) => {
+                // Files that could not be opened are tried again on their next line.
+                files.retain(|_, file| file.is_some());
+                let errors = files
+                    .values_mut()
+                    .flatten()
+                    .filter_map(|file| {
+                        let err = file.reopen().err()?;
+                        Some(format!("can't reopen `{}`: {err}", file.path().display()))
+                    })
+                    .collect();
+                let _ = ack.send(errors);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, os::fd::FromRawFd};
+
+    use super::*;
+
+    fn name() -> ProcessName {
+        ProcessName {
+            name: Arc::from("web"),
+            index: 0,
+        }
+    }
+
+    /// Returns the read and write ends of a new pipe.
+    fn pipe() -> (OwnedFd, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    /// Captures a new pipe as `stream`, and returns its write end.
+    fn capture(output: &mut Output, reactor: &Arc<Reactor>, stream: Stream) -> File {
+        let (read_end, write_end) = pipe();
+        output.capture(reactor, &name(), stream, read_end).unwrap();
+        write_end
+    }
+
+    #[test]
+    fn read_lines_splits_lines() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let mut output = Output::default();
+        let mut writer = capture(&mut output, &reactor, Stream::Stdout);
+        let pipe = &mut output.pipes[0];
+
+        assert_eq!(pipe.read_lines(), (Vec::new(), false));
+        writer.write_all(b"one\n\ntwo\nthr").unwrap();
+        assert_eq!(
+            pipe.read_lines(),
+            (vec!["one".into(), "".into(), "two".into()], false)
+        );
+        writer.write_all(b"ee\n").unwrap();
+        assert_eq!(pipe.read_lines(), (vec!["three".into()], false));
+    }
+
+    #[test]
+    fn read_lines_splits_long_lines() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let mut output = Output::default();
+        let mut writer = capture(&mut output, &reactor, Stream::Stdout);
+        let pipe = &mut output.pipes[0];
+
+        writer.write_all(&[b'a'; MAX_LINE_LEN + 5]).unwrap();
+        writer.write_all(b"\n").unwrap();
+        assert_eq!(pipe.read_lines(), (vec!["a".repeat(MAX_LINE_LEN)], false));
+        assert_eq!(pipe.read_lines(), (vec!["a".repeat(5)], false));
+    }
+
+    #[test]
+    fn read_lines_flushes_partial_line_when_closed() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let mut output = Output::default();
+        let mut writer = capture(&mut output, &reactor, Stream::Stdout);
+        let pipe = &mut output.pipes[0];
+
+        writer.write_all(b"first\nlast").unwrap();
+        drop(writer);
+        assert_eq!(
+            pipe.read_lines(),
+            (vec!["first".into(), "last".into()], true)
+        );
+        assert!(pipe.partial.is_empty());
+    }
+
+    #[test]
+    fn read_lines_reads_a_few_times_per_wakeup() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let mut output = Output::default();
+        let mut writer = capture(&mut output, &reactor, Stream::Stdout);
+        let pipe = &mut output.pipes[0];
+
+        // Each line fills a read.
+        let line = "x".repeat(4095);
+        for _ in 0..READS_PER_WAKEUP + 2 {
+            writeln!(writer, "{line}").unwrap();
+        }
+        drop(writer);
+        assert_eq!(
+            pipe.read_lines(),
+            (vec![line.clone(); READS_PER_WAKEUP], false)
+        );
+        assert_eq!(pipe.read_lines(), (vec![line; 2], true));
+    }
+
+    #[test]
+    fn read_keeps_followers_of_other_stream() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let mut output = Output::default();
+        let mut stdout = capture(&mut output, &reactor, Stream::Stdout);
+        let mut stderr = capture(&mut output, &reactor, Stream::Stderr);
+        let stdout_token = output.pipes[0].registration.token();
+        let stderr_token = output.pipes[1].registration.token();
+
+        let (gone_sender, gone_receiver) = mpsc::channel();
+        drop(gone_receiver);
+        let (stdout_sender, stdout_receiver) = mpsc::channel();
+        let (stderr_sender, stderr_receiver) = mpsc::channel();
+        output.follow(Stream::Stdout, gone_sender);
+        output.follow(Stream::Stdout, stdout_sender);
+        output.follow(Stream::Stderr, stderr_sender);
+
+        stdout.write_all(b"out\n").unwrap();
+        let lines = output.read(stdout_token, &name());
+        assert_eq!(lines.len(), 1);
+        assert_eq!(output.followers.len(), 2);
+        assert_eq!(stdout_receiver.try_recv().unwrap().text, "out");
+        assert!(stderr_receiver.try_recv().is_err());
+
+        stderr.write_all(b"err\n").unwrap();
+        output.read(stderr_token, &name());
+        assert_eq!(stderr_receiver.try_recv().unwrap().text, "err");
+        assert!(stdout_receiver.try_recv().is_err());
+        assert_eq!(output.lines(Stream::Stdout).len(), 1);
+        assert_eq!(output.lines(Stream::Stderr).len(), 1);
+    }
+}
@@ -1,16 +1,17 @@
 //! This module is used to control the lifetime of a running program.
 
 use std::{
+    collections::{hash_map::RandomState, BTreeSet},
     error::Error,
     ffi::c_int,
     fmt::Display,
-    fs::{File, OpenOptions},
-    os::unix::process::CommandExt,
+    hash::{BuildHasher, Hasher},
+    os::{fd::OwnedFd, unix::process::CommandExt},
     path::Path,
     process::Command,
     sync::{
-        atomic::{AtomicBool, Ordering::Relaxed},
-        Arc, Condvar, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex, RwLock,
     },
     time::{Duration, Instant},
 };
@@ -18,43 +19,40 @@ use std::{
 use libc::pid_t;
 
 use crate::{
-    config::{ProgramConfig, RestartPolicy, StopSignal},
+    config::{HealthAction, Probe, ProgramConfig, RestartPolicy},
+    health::{self, Check, NetworkProbe, ProbeResult},
     logs::{LogEvent, LogEventKind},
+    notify::{Notification, NotifySocket},
+    output::{Output, OutputMessage, OutputSender, Stream},
+    reactor::Reactor,
+    signal::Signal,
     LogSender,
 };
 
-/// Opens a file for appending.
-fn open_append(path: &Path) -> std::io::Result<File> {
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(path)
-}
-
 /// Creates a command from a program configuration.
 ///
-/// The returned command can be invoked to start the program once.
-fn create_command(config: &ProgramConfig) -> Result<Command, Box<dyn Error>> {
+/// The returned command can be invoked to start the program once. `notify_socket` is exported in
+/// `NOTIFY_SOCKET`.
+fn create_command(
+    config: &ProgramConfig,
+    notify_socket: Option<&str>,
+) -> Result<Command, Box<dyn Error>> {
     let mut command = std::process::Command::new(&config.command);
 
     command.args(&config.args);
     command.env_clear();
     command.envs(&config.environment);
-
-    if let Some(stdout) = &config.stdout {
-        let file = open_append(&stdout)?;
-        command.stdout(file);
-    } else {
-        command.stdout(std::process::Stdio::null());
+    if let Some(notify_socket) = notify_socket {
+        command.env("NOTIFY_SOCKET", notify_socket);
+        if let Some(timeout) = config.watchdog_timeout {
+            let usec = duration_from_f64(timeout).as_micros();
+            command.env("WATCHDOG_USEC", usec.to_string());
+        }
     }
 
-    if let Some(stderr) = &config.stderr {
-        let file = open_append(&stderr)?;
-        command.stderr(file);
-    } else {
-        command.stderr(std::process::Stdio::null());
-    }
+    // The output is captured by the supervisor, which logs it.
+    command.stdout(std::process::Stdio::piped());
+    command.stderr(std::process::Stdio::piped());
 
     if let Some(stdin) = &config.stdin {
         let file = std::fs::File::open(stdin)?;
@@ -76,12 +74,36 @@ fn create_command(config: &ProgramConfig) -> Result<Command, Box<dyn Error>> {
         }
     }
 
+    // Each process leads its own process group, so that it can be signaled along with its
+    // descendants, and so that signals sent to the supervisor's group do not reach it.
+    command.process_group(0);
+
+    unblock_signals(&mut command);
+
     Ok(command)
 }
 
+/// Makes a command unblock every signal before it is executed.
+///
+/// The supervisor blocks the signals handled by the reactor, and `Command` leaves the signal mask
+/// of the child as it is, so `SIGTERM` and `SIGINT` would otherwise never be delivered to it.
+pub fn unblock_signals(command: &mut Command) {
+    unsafe {
+        command.pre_exec(|| {
+            let mut mask = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut mask);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut());
+            Ok(())
+        });
+    }
+}
+
 /// Sends a signal to a running process.
-fn send_signal(pid: libc::pid_t, signal: StopSignal) -> Result<(), ProcessError> {
-    let ret = unsafe { libc::kill(pid, signal.as_raw_signal()) };
+///
+/// When `as_group` is set, the signal is sent to the whole process group the process leads.
+fn send_signal(pid: libc::pid_t, signal: Signal, as_group: bool) -> Result<(), ProcessError> {
+    let target = if as_group { -pid } else { pid };
+    let ret = unsafe { libc::kill(target, signal.0) };
     if ret != 0 {
         Err(ProcessError::NotStarted)
     } else {
@@ -117,17 +139,6 @@ impl From<std::io::Error> for ProcessError {
     }
 }
 
-/// Waits for a process to exit.
-fn wait_pid(pid: libc::pid_t) -> std::io::Result<ExitCode> {
-    let mut status = 0;
-    let ret = unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) };
-    if ret == -1 {
-        Err(std::io::Error::last_os_error())
-    } else {
-        Ok(ExitCode(status))
-    }
-}
-
 /// The exit code of a process.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ExitCode(pub c_int);
@@ -145,6 +156,11 @@ impl ExitCode {
             status as u32
         }
     }
+
+    /// Returns the signal that terminated the process, if any.
+    pub fn signal(self) -> Option<Signal> {
+        libc::WIFSIGNALED(self.0).then(|| Signal(libc::WTERMSIG(self.0)))
+    }
 }
 
 impl Display for ExitCode {
@@ -154,59 +170,17 @@ impl Display for ExitCode {
         if libc::WIFEXITED(st) {
             write!(f, "exited with code {}", libc::WEXITSTATUS(st))
         } else if libc::WIFSIGNALED(st) {
-            let signal = match libc::WTERMSIG(st) {
-                libc::SIGABRT => "SIGABRT",
-                libc::SIGALRM => "SIGALRM",
-                libc::SIGBUS => "SIGBUS",
-                libc::SIGCHLD => "SIGCHLD",
-                libc::SIGCONT => "SIGCONT",
-                libc::SIGFPE => "SIGFPE",
-                libc::SIGHUP => "SIGHUP",
-                libc::SIGILL => "SIGILL",
-                libc::SIGINT => "SIGINT",
-                libc::SIGKILL => "SIGKILL",
-                libc::SIGPIPE => "SIGPIPE",
-                libc::SIGQUIT => "SIGQUIT",
-                libc::SIGSEGV => "SIGSEGV",
-                libc::SIGSTOP => "SIGSTOP",
-                libc::SIGTERM => "SIGTERM",
-                libc::SIGTSTP => "SIGTSTP",
-                libc::SIGTTIN => "SIGTTIN",
-                libc::SIGTTOU => "SIGTTOU",
-                libc::SIGUSR1 => "SIGUSR1",
-                libc::SIGUSR2 => "SIGUSR2",
-                libc::SIGPROF => "SIGPROF",
-                libc::SIGSYS => "SIGSYS",
-                libc::SIGTRAP => "SIGTRAP",
-                libc::SIGURG => "SIGURG",
-                libc::SIGVTALRM => "SIGVTALRM",
-                libc::SIGXCPU => "SIGXCPU",
-                libc::SIGXFSZ => "SIGXFSZ",
-                _ => "unknown",
-            };
+            let signal = Signal(libc::WTERMSIG(st));
 
             write!(f, "terminated by signal {signal}")
         } else if libc::WIFSTOPPED(st) {
-            write!(f, "stopped by signal {}", libc::WSTOPSIG(st))
+            write!(f, "stopped by signal {}", Signal(libc::WSTOPSIG(st)))
         } else {
             write!(f, "unknown exit status: {}", st)
         }
     }
 }
 
-/// The state of the observer thread.
-#[derive(Debug)]
-pub struct ObserverState {
-    /// Whether the process structure itself is being removed.
-    pub process_removed: bool,
-    /// Whether new processes should spawned when possible.
-    pub standby: bool,
-    /// Whether the process must be restarted regardless of its exit code.
-    ///
-    /// This also resets the restart count.
-    pub restart: bool,
-}
-
 /// The name of a running process.
 ///
 /// This includes its name in the configuration, as well as its replication index.
@@ -218,6 +192,57 @@ pub struct ProcessName {
     pub index: usize,
 }
 
+impl ProcessName {
+    /// Returns whether the process is designated by `pattern`.
+    ///
+    /// A pattern is the name of a program, the name of a replica (`web-1` or `web:1`), or a glob
+    /// matched against either of them (`worker-*`). With a colon, the program and the index are
+    /// matched separately, which is unambiguous when a program name ends with `-<number>`.
+    pub fn matches(&self, pattern: &str) -> bool {
+        match pattern.rsplit_once(':') {
+            Some((name, index)) => {
+                glob_match(name, &self.name) && glob_match(index, &self.index.to_string())
+            }
+            None => glob_match(pattern, &self.name) || glob_match(pattern, &self.to_string()),
+        }
+    }
+}
+
+/// Returns whether `text` matches `pattern`, in which `*` matches any sequence of characters and
+/// `?` matches any single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // The position after the last `*` seen, and the position in `text` it currently matches up to.
+    let mut backtrack = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` match one more character.
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 impl Display for ProcessName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let buf = format!("{}-{}", self.name, self.index);
@@ -225,11 +250,18 @@ impl Display for ProcessName {
     }
 }
 
+/// Identifies a single run of a process.
+///
+/// Run identifiers are unique across all processes, so that a timer scheduled for a run that has
+/// ended is never mistaken for one of the current run.
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Debug)]
 /// Information about the process that is currently running.
 pub struct RunningProcess {
     pub started_at: Instant,
     pub pid: pid_t,
+    pub run_id: u64,
 }
 
 impl RunningProcess {
@@ -237,79 +269,304 @@ impl RunningProcess {
         Self {
             started_at: Instant::now(),
             pid,
+            run_id: NEXT_RUN_ID.fetch_add(1, Relaxed),
+        }
+    }
+}
+
+/// What to do when a timer scheduled by a process fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerKind {
+    /// The process has been running for `healthy_uptime`.
+    Healthy,
+    /// The process has been asked to stop, and the current step of the stop sequence has timed
+    /// out.
+    ///
+    /// This holds the index of the next step. Adopted descendants are stopped as well, even if
+    /// the run has ended.
+    Escalate(usize),
+    /// The process has waited long enough before being restarted.
+    Backoff,
+    /// The health of the process must be checked.
+    Probe,
+    /// The command probe with the given PID has not completed in time.
+    ProbeTimeout(pid_t),
+    /// The process must have sent a keepalive since the timer was scheduled.
+    Watchdog,
+}
+
+/// The state of a process, as reported by `status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    /// The process has never been started, or has been stopped on purpose.
+    Stopped,
+    /// The process has been asked to start, and waits for the programs it depends on to be
+    /// running.
+    Waiting,
+    /// The process is running, but has not been up for `healthy_uptime` yet, or has not reported
+    /// being ready.
+    Starting,
+    /// The process is running and healthy.
+    Running,
+    /// The process is running, but too many consecutive health probes have failed.
+    Unhealthy,
+    /// The process has failed, and is waiting before being restarted.
+    Backoff,
+    /// The process has been asked to stop and has not exited yet.
+    Stopping,
+    /// The process has exited on its own and will not be restarted.
+    Exited,
+    /// The process could not be kept running, and has been given up on.
+    Fatal,
+}
+
+impl Display for ProcessStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProcessStatus::Stopped => "STOPPED",
+            ProcessStatus::Waiting => "WAITING",
+            ProcessStatus::Starting => "STARTING",
+            ProcessStatus::Running => "RUNNING",
+            ProcessStatus::Unhealthy => "UNHEALTHY",
+            ProcessStatus::Backoff => "BACKOFF",
+            ProcessStatus::Stopping => "STOPPING",
+            ProcessStatus::Exited => "EXITED",
+            ProcessStatus::Fatal => "FATAL",
+        };
+        f.pad(name)
+    }
+}
+
+/// The supervision state of a process.
+#[derive(Debug)]
+pub struct Lifecycle {
+    /// The current state of the process.
+    pub status: ProcessStatus,
+    /// The instant at which the process entered its current state.
+    pub since: Instant,
+    /// Whether the process should be kept stopped.
+    pub standby: bool,
+    /// Whether the process must be started again once it exits, regardless of its exit code.
+    ///
+    /// This also resets the restart count.
+    pub restart: bool,
+    /// The number of consecutive failed starts, reset once the process is healthy.
+    pub retry_count: u32,
+    /// The number of consecutive failed health probes of the current run.
+    pub failed_probes: u32,
+    /// The process that is currently running.
+    pub running: Option<RunningProcess>,
+    /// How the last run of the process ended.
+    pub last_exit: Option<ExitCode>,
+    /// Identifies the pending restart while in [`ProcessStatus::Backoff`].
+    pub backoff_id: u64,
+    /// The process group of the latest run, which the descendants of the process inherit.
+    pub group: Option<pid_t>,
+    /// The descendants of the process that outlived their parent and have been adopted by the
+    /// supervisor.
+    pub strays: BTreeSet<pid_t>,
+    /// The socket on which the process sends its notifications, once it has been created.
+    pub notify: Option<NotifySocket>,
+    /// The status last reported by the process through `STATUS=`.
+    pub status_text: Option<String>,
+    /// The instant at which the running process last sent a keepalive through `WATCHDOG=1`.
+    pub keepalive: Instant,
+    /// Whether the running process has been stopped for not sending keepalives.
+    ///
+    /// Its exit is then a failure, whatever its exit code.
+    pub hung: bool,
+    /// Whether the stop sequence has been started, since the latest run started or ended.
+    ///
+    /// Another stop request must not start it over, which would escalate early.
+    pub stop_started: bool,
+}
+
+impl Lifecycle {
+    /// Moves the process to a new state.
+    pub fn transition(&mut self, status: ProcessStatus) {
+        if self.status != status {
+            self.status = status;
+            self.since = Instant::now();
+        }
+    }
+
+    /// Cancels the pending start of a process in [`ProcessStatus::Backoff`] or
+    /// [`ProcessStatus::Waiting`].
+    ///
+    /// Returns whether a start was pending.
+    pub fn cancel_pending_start(&mut self) -> bool {
+        if !matches!(self.status, ProcessStatus::Backoff | ProcessStatus::Waiting) {
+            return false;
+        }
+
+        self.standby = true;
+        self.transition(ProcessStatus::Stopped);
+        true
+    }
+
+    /// Returns whether the run `run_id` is still checked by health probes and by the watchdog.
+    pub fn is_monitored(&self, run_id: u64) -> bool {
+        self.status != ProcessStatus::Stopping
+            && self.running.as_ref().is_some_and(|r| r.run_id == run_id)
+    }
+
+    /// Returns whether `pid` is the running process, or one of its descendants: a member of the
+    /// process group of its run, or an adopted stray.
+    pub fn is_descendant(&self, pid: pid_t) -> bool {
+        self.running
+            .as_ref()
+            .is_some_and(|running| running.pid == pid)
+            || self
+                .group
+                .is_some_and(|group| unsafe { libc::getpgid(pid) } == group)
+            || self.strays.contains(&pid)
+    }
+
+    /// Sends `signal` to the running process, or to the process group of its run when
+    /// `as_group` is set.
+    pub fn signal_running(&self, signal: Signal, as_group: bool) -> Result<(), ProcessError> {
+        let running = self.running.as_ref().ok_or(ProcessError::NotStarted)?;
+        let Some(group) = self.group.filter(|_| as_group) else {
+            return send_signal(running.pid, signal, false);
+        };
+
+        let sent = send_signal(group, signal, true);
+        // A main process announced through `MAINPID=` may have left the group.
+        if unsafe { libc::getpgid(running.pid) } != group {
+            return send_signal(running.pid, signal, false).or(sent);
         }
+        sent
     }
+
+    /// Sends `signal` to the adopted descendants of the process.
+    ///
+    /// Those that are still in the group of the running process are skipped when the group is
+    /// signaled as a whole.
+    pub fn signal_strays(&self, signal: Signal, as_group: bool) {
+        let group = self.running.as_ref().filter(|_| as_group).and(self.group);
+        for &pid in &self.strays {
+            if group.is_some_and(|group| unsafe { libc::getpgid(pid) } == group) {
+                continue;
+            }
+            let _ = send_signal(pid, signal, false);
+        }
+    }
+}
+
+/// Computes the delay before the `attempt`-th consecutive restart of a process.
+fn backoff_delay(config: &ProgramConfig, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+    let delay = config.backoff_initial * config.backoff_multiplier.max(1.0).powi(exponent);
+    let delay = delay.min(config.backoff_max);
+
+    let jitter = config.backoff_jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+        return duration_from_f64(delay);
+    }
+
+    // `RandomState` is seeded randomly, which is all the randomness needed here.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    duration_from_f64(delay * (1.0 + jitter * (2.0 * random - 1.0)))
 }
 
 #[derive(Debug)]
-/// The state that is shared between the main thread and background threads.
+/// The state of a process, shared between the commands and the reactor.
 pub struct ProcessState {
     /// The name of the process.
     pub name: ProcessName,
     /// The configuration of the process.
     pub config: RwLock<ProgramConfig>,
-
-    /// Whether the process wants to be running.
-    pub observer_state: Mutex<ObserverState>,
-    /// A condition variable that is notified when the process wants to be running.
-    pub observer_state_cond: Condvar,
-
-    /// The PID of the process.
-    pub pid: Mutex<Option<RunningProcess>>,
+    /// The supervision state of the process.
+    pub lifecycle: Mutex<Lifecycle>,
+    /// The output captured from the process.
+    pub output: Mutex<Output>,
 }
 
 impl ProcessState {
-    /// Updates the observer state and notifies the condition variable.
-    pub fn update_observer_state(&self, f: impl FnOnce(&mut ObserverState)) {
-        let mut state = self.observer_state.lock().unwrap();
-        f(&mut state);
-        self.observer_state_cond.notify_all();
+    /// Sends `signal` to the running process alone.
+    ///
+    /// Unlike the stop signals, it is never sent to the whole group, whatever `stop_as_group`
+    /// and `kill_as_group` say.
+    pub fn send_signal(&self, signal: Signal) -> Result<(), ProcessError> {
+        let lifecycle = self.lifecycle.lock().unwrap();
+        lifecycle.signal_running(signal, false)
     }
 
-    pub fn send_stop_signal(&self, signal: StopSignal) -> Result<(), ProcessError> {
-        let running_process = self.pid.lock().unwrap();
-        let running_process = running_process.as_ref().ok_or(ProcessError::NotStarted)?;
-        send_signal(running_process.pid, signal)
+    /// Sends `signal` to the running process and to its adopted descendants, and keeps it
+    /// stopped once it exits.
+    pub fn stop_with(&self, signal: Signal) -> Result<(), ProcessError> {
+        let as_group = self.config.read().unwrap().signals_group(signal);
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        lifecycle.signal_strays(signal, as_group);
+        if lifecycle.running.is_none() {
+            lifecycle.cancel_pending_start();
+            if lifecycle.strays.is_empty() {
+                return Err(ProcessError::NotStarted);
+            }
+            return Ok(());
+        }
+        lifecycle.signal_running(signal, as_group)?;
+        lifecycle.standby = true;
+        lifecycle.restart = false;
+        lifecycle.transition(ProcessStatus::Stopping);
+        Ok(())
     }
 
     pub fn force_stop(&self) -> Result<(), ProcessError> {
-        self.update_observer_state(|s| s.standby = true);
-        self.send_stop_signal(StopSignal::Kill)
+        self.stop_with(Signal::KILL)
     }
 }
+
 #[derive(Debug)]
 pub struct Process {
-    pub state: Arc<ProcessState>,
+    pub state: ProcessState,
     log_sender: LogSender,
+    output_sender: OutputSender,
+    reactor: Arc<Reactor>,
 }
 
 impl Process {
     /// Creates a new [`Process`] from its configuration.
+    ///
+    /// The process is not started, even if `at_launch` is set.
     #[inline]
-    pub fn new(log_sender: LogSender, name: ProcessName, config: ProgramConfig) -> Self {
-        let start_now = config.at_launch;
-
-        let state = Arc::new(ProcessState {
+    pub fn new(
+        log_sender: LogSender,
+        output_sender: OutputSender,
+        reactor: Arc<Reactor>,
+        name: ProcessName,
+        config: ProgramConfig,
+    ) -> Self {
+        let state = ProcessState {
             name,
             config: RwLock::new(config),
-
-            observer_state: Mutex::new(ObserverState {
-                process_removed: false,
-                standby: !start_now,
+            lifecycle: Mutex::new(Lifecycle {
+                status: ProcessStatus::Stopped,
+                since: Instant::now(),
+                standby: true,
                 restart: false,
+                retry_count: 0,
+                failed_probes: 0,
+                running: None,
+                last_exit: None,
+                backoff_id: 0,
+                group: None,
+                strays: BTreeSet::new(),
+                notify: None,
+                status_text: None,
+                keepalive: Instant::now(),
+                hung: false,
+                stop_started: false,
             }),
-            observer_state_cond: Condvar::new(),
-
-            pid: Mutex::new(None),
-        });
-
-        std::thread::spawn({
-            let state = Arc::clone(&state);
-            let log_sender = log_sender.clone();
-            move || process_observer(log_sender, state)
-        });
+            output: Mutex::default(),
+        };
 
-        Self { state, log_sender }
+        Self {
+            state,
+            log_sender,
+            output_sender,
+            reactor,
+        }
     }
 
     /// Returns the name of the process.
@@ -324,68 +581,154 @@ impl Process {
         &self.state.config
     }
 
-    /// Returns whether the process is running.
+    /// Returns the PID of the process, if it is running.
+    #[inline]
+    pub fn pid(&self) -> Option<pid_t> {
+        self.state
+            .lifecycle
+            .lock()
+            .unwrap()
+            .running
+            .as_ref()
+            .map(|r| r.pid)
+    }
+
+    /// Returns the current state of the process.
     #[inline]
-    pub fn is_running(&self) -> bool {
-        self.state.pid.lock().unwrap().is_some()
+    pub fn status(&self) -> ProcessStatus {
+        self.state.lifecycle.lock().unwrap().status
+    }
+
+    /// Returns whether the last run of the process ended with `SIGKILL`.
+    pub fn was_killed(&self) -> bool {
+        self.state
+            .lifecycle
+            .lock()
+            .unwrap()
+            .last_exit
+            .is_some_and(|status| status.signal() == Some(Signal::KILL))
+    }
+
+    /// Returns whether the process or one of its adopted descendants is still running.
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        let lifecycle = self.state.lifecycle.lock().unwrap();
+        lifecycle.running.is_some() || !lifecycle.strays.is_empty()
+    }
+
+    /// Returns the process group of the latest run of the process, which its descendants inherit.
+    #[inline]
+    pub fn group(&self) -> Option<pid_t> {
+        self.state.lifecycle.lock().unwrap().group
+    }
+
+    /// Claims an orphan that has been reparented to the supervisor, if it belongs to the process
+    /// group of this process.
+    ///
+    /// Returns whether the orphan belongs to this process.
+    pub fn adopt(&self, pid: pid_t, group: pid_t) -> bool {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if lifecycle.running.as_ref().is_some_and(|r| r.pid == pid) {
+            return true;
+        }
+        if lifecycle.group != Some(group) {
+            return false;
+        }
+        if lifecycle.strays.insert(pid) {
+            self.log(LogEventKind::Adopted(pid));
+        }
+        true
+    }
+
+    /// Forgets an adopted descendant that has exited.
+    ///
+    /// Returns whether it was a descendant of this process.
+    pub fn release_stray(&self, pid: pid_t) -> bool {
+        self.state.lifecycle.lock().unwrap().strays.remove(&pid)
+    }
+
+    /// Sends an event about this process to the log thread.
+    fn log(&self, kind: LogEventKind) {
+        let _ = self.log_sender.send(
+            LogEvent {
+                kind,
+                time: Instant::now(),
+                name: self.state.name.clone(),
+            }
+            .into(),
+        );
     }
 
     /// Requests the process to start.
+    ///
+    /// A process waiting to be restarted is started right away.
     pub fn launch(&self) -> Result<(), ProcessError> {
-        if self.state.pid.lock().unwrap().is_some() {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if lifecycle.running.is_some() {
+            return Err(ProcessError::AlreadyStarted);
+        }
+
+        lifecycle.standby = false;
+        self.spawn(&mut lifecycle);
+        Ok(())
+    }
+
+    /// Marks the process as waiting for the programs it depends on.
+    ///
+    /// It is started by [`Process::start_waiting`] once they are running.
+    pub fn wait_for_dependencies(&self) -> Result<(), ProcessError> {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if lifecycle.running.is_some() {
             return Err(ProcessError::AlreadyStarted);
         }
 
-        self.state.update_observer_state(|s| s.standby = false);
+        lifecycle.standby = false;
+        lifecycle.transition(ProcessStatus::Waiting);
         Ok(())
     }
 
+    /// Starts the process if it is still waiting for the programs it depends on.
+    pub fn start_waiting(&self) {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if lifecycle.status == ProcessStatus::Waiting {
+            self.spawn(&mut lifecycle);
+        }
+    }
+
     /// Requests the process to stop.
     ///
-    /// This function will also create a new thread in order to check if the process has been
-    /// correctly stopped after the configured timeout.
+    /// The process goes through its stop sequence, and is killed if it is still running after
+    /// the last step.
     pub fn request_stop(&self) -> Result<(), ProcessError> {
-        let state = self.state.clone();
-        let log_sender = self.log_sender.clone();
-
-        state.send_stop_signal(self.state.config.read().unwrap().signal)?;
-        state.update_observer_state(|s| s.standby = true);
-
-        std::thread::spawn(move || {
-            // The process is still running because the STARTED instant is still in the past.
-            // ---------------------------------------------->
-            //          |                |
-            //        STARTED          STOP REQUEST
-            //
-            // The process has stoped because the STARTED instant is in the future.
-            // ---------------------------------------------->
-            //         |                |
-            //      STOP REQUEST      STARTED
-
-            let stop_request_instant = Instant::now();
-
-            std::thread::sleep(duration_from_f64(state.config.read().unwrap().exit_timeout));
-
-            let running_process = state.pid.lock().unwrap();
-            if running_process
-                .as_ref()
-                .is_some_and(|running_process| running_process.started_at < stop_request_instant)
-            {
-                drop(running_process);
-
-                if let Err(err) = state.force_stop() {
-                    println!("failed to force_stop: {}", err);
-                }
-                log_sender
-                    .send(LogEvent {
-                        kind: LogEventKind::Killed,
-                        time: Instant::now(),
-                        name: state.name.clone(),
-                    })
-                    .unwrap();
+        let config = self.state.config.read().unwrap();
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+
+        // The stop sequence under way goes on, but a pending restart is overridden.
+        if lifecycle.stop_started {
+            lifecycle.standby = true;
+            lifecycle.restart = false;
+            return Ok(());
+        }
+
+        let Some(running) = lifecycle.running.as_ref() else {
+            let was_pending = lifecycle.cancel_pending_start();
+            if !lifecycle.strays.is_empty() {
+                // There is no run to attach the timers to, and a fresh identifier never matches
+                // one.
+                let run_id = NEXT_RUN_ID.fetch_add(1, Relaxed);
+                self.stop_step(&config, &mut lifecycle, run_id, 0)?;
+            } else if !was_pending {
+                return Err(ProcessError::NotStarted);
             }
-        });
+            return Ok(());
+        };
+        let run_id = running.run_id;
+        self.stop_step(&config, &mut lifecycle, run_id, 0)?;
 
+        // A pending restart is overridden.
+        lifecycle.standby = true;
+        lifecycle.restart = false;
+        lifecycle.transition(ProcessStatus::Stopping);
         Ok(())
     }
 
@@ -395,157 +738,756 @@ impl Process {
     }
 
     /// Requests the process to restart.
+    ///
+    /// A process that is not running is simply started.
     pub fn request_restart(&self) -> Result<(), ProcessError> {
-        self.state.update_observer_state(|s| {
-            s.standby = true;
-            s.restart = true;
-        });
-        self.state
-            .send_stop_signal(self.state.config.read().unwrap().signal)
+        let config = self.state.config.read().unwrap();
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+
+        let Some(running) = lifecycle.running.as_ref() else {
+            lifecycle.standby = false;
+            lifecycle.retry_count = 0;
+            self.spawn(&mut lifecycle);
+            return Ok(());
+        };
+
+        let run_id = running.run_id;
+        if !lifecycle.stop_started {
+            self.stop_step(&config, &mut lifecycle, run_id, 0)?;
+        }
+
+        lifecycle.standby = true;
+        lifecycle.restart = true;
+        lifecycle.transition(ProcessStatus::Stopping);
+        Ok(())
     }
 
     /// Forces the process to restart.
     pub fn force_restart(&self) -> Result<(), ProcessError> {
-        self.state.update_observer_state(|s| {
-            s.standby = true;
-            s.restart = true;
-        });
-        self.state.send_stop_signal(StopSignal::Kill)
+        let as_group = self.state.config.read().unwrap().kill_as_group;
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        lifecycle.signal_running(Signal::KILL, as_group)?;
+
+        lifecycle.standby = true;
+        lifecycle.restart = true;
+        lifecycle.transition(ProcessStatus::Stopping);
+        Ok(())
     }
-}
 
-impl Drop for Process {
-    fn drop(&mut self) {
-        let _ = self.force_stop();
-        self.state
-            .update_observer_state(|s| s.process_removed = true);
+    /// Sends the signal of the `step`-th step of the stop sequence to the process and to its
+    /// adopted descendants, and schedules the next step.
+    ///
+    /// Past the last step, the process is killed.
+    fn stop_step(
+        &self,
+        config: &ProgramConfig,
+        lifecycle: &mut Lifecycle,
+        run_id: u64,
+        step: usize,
+    ) -> Result<(), ProcessError> {
+        let next = config.stop_step(step);
+        let signal = next.map_or(Signal::KILL, |next| next.signal);
+        let as_group = config.signals_group(signal);
+
+        lifecycle.signal_strays(signal, as_group);
+        if lifecycle.running.is_some() {
+            lifecycle.signal_running(signal, as_group)?;
+        }
+        lifecycle.stop_started = true;
+
+        match next {
+            Some(next) => {
+                if step > 0 {
+                    self.log(LogEventKind::Escalated(signal));
+                }
+                self.reactor.schedule(
+                    Instant::now() + duration_from_f64(next.timeout),
+                    self.state.name.clone(),
+                    run_id,
+                    TimerKind::Escalate(step + 1),
+                );
+            }
+            None => self.log(LogEventKind::Killed),
+        }
+        Ok(())
     }
-}
 
-/// Observes a running process. This should be running in a background thread.
-fn process_observer(log_sender: LogSender, state: Arc<ProcessState>) {
-    let mut command = match create_command(&state.config.read().unwrap()) {
-        Ok(ok) => ok,
-        Err(err) => {
-            log_sender
-                .send(LogEvent {
-                    kind: LogEventKind::Failed(format!("can't create command: {err}")),
-                    time: Instant::now(),
-                    name: state.name.clone(),
-                })
-                .unwrap();
-            return;
+    /// Moves on to the `step`-th step of the stop sequence of the run `run_id`.
+    fn escalate(&self, run_id: u64, step: usize) {
+        let config = self.state.config.read().unwrap();
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+
+        // Once the run has ended, only its adopted descendants are left to stop. The sequence
+        // is abandoned once a new run has started.
+        match &lifecycle.running {
+            Some(running) if running.run_id != run_id => return,
+            None if lifecycle.strays.is_empty() => return,
+            _ => (),
         }
-    };
 
-    let healthy_uptime = duration_from_f64(state.config.read().unwrap().healthy_uptime);
+        if let Err(err) = self.stop_step(&config, &mut lifecycle, run_id, step) {
+            self.log(LogEventKind::Failed(format!("can't stop: {err}")));
+        }
+    }
 
-    let mut retry_count = 0;
+    /// Spawns a new instance of the process.
+    fn spawn(&self, lifecycle: &mut Lifecycle) {
+        let config = self.state.config.read().unwrap();
+
+        if config.notify && lifecycle.notify.is_none() {
+            match NotifySocket::bind(&self.reactor, &self.state.name) {
+                Ok(socket) => lifecycle.notify = Some(socket),
+                Err(err) => {
+                    self.log(LogEventKind::Failed(format!(
+                        "can't create notify socket: {err}"
+                    )));
+                    lifecycle.standby = true;
+                    lifecycle.transition(ProcessStatus::Fatal);
+                    return;
+                }
+            }
+        }
 
-    'main: loop {
-        // Wait until we need to do something.
-        {
-            let mut lock = state.observer_state.lock().unwrap();
-            while !lock.process_removed && lock.standby && !lock.restart {
-                lock = state.observer_state_cond.wait(lock).unwrap();
+        let notify_socket = lifecycle.notify.as_ref().map(NotifySocket::address);
+        let mut command = match create_command(&config, notify_socket) {
+            Ok(ok) => ok,
+            Err(err) => {
+                self.log(LogEventKind::Failed(format!("can't create command: {err}")));
+                lifecycle.standby = true;
+                lifecycle.transition(ProcessStatus::Fatal);
+                return;
             }
-            if lock.process_removed {
-                break 'main;
+        };
+
+        let mut child = match self.reactor.spawn(&mut command) {
+            Ok(child) => child,
+            Err(err) => {
+                self.log(LogEventKind::Failed(format!(
+                    "Can't spawn child process: {err}"
+                )));
+                lifecycle.standby = true;
+                lifecycle.transition(ProcessStatus::Fatal);
+                return;
             }
-            if lock.restart {
-                lock.restart = false;
-                retry_count = 0;
+        };
+
+        let pid = child.id() as libc::pid_t;
+        let mut output = self.state.output.lock().unwrap();
+        let pipes = [
+            (Stream::Stdout, child.stdout.take().map(OwnedFd::from)),
+            (Stream::Stderr, child.stderr.take().map(OwnedFd::from)),
+        ];
+        for (stream, fd) in pipes {
+            let Some(fd) = fd else {
+                continue;
+            };
+            if let Err(err) = output.capture(&self.reactor, &self.state.name, stream, fd) {
+                self.log(LogEventKind::Failed(format!(
+                    "can't capture {stream}: {err}"
+                )));
             }
         }
-        let pid = match command.spawn() {
-            Ok(child) => child.id() as libc::pid_t,
-            Err(err) => {
-                log_sender
-                    .send(LogEvent {
-                        kind: LogEventKind::Failed(format!("Can't spawn child process: {err}")),
-                        time: Instant::now(),
-                        name: state.name.clone(),
-                    })
-                    .unwrap();
-                state.update_observer_state(|s| s.standby = true);
+        drop(output);
+
+        let running = RunningProcess::started_right_now(pid);
+        lifecycle.group = Some(pid);
+        lifecycle.status_text = None;
+        lifecycle.keepalive = running.started_at;
+        lifecycle.hung = false;
+        lifecycle.stop_started = false;
+
+        if let Some(timeout) = config.watchdog_timeout {
+            self.reactor.schedule(
+                running.started_at + duration_from_f64(timeout),
+                self.state.name.clone(),
+                running.run_id,
+                TimerKind::Watchdog,
+            );
+        }
+
+        if let Some(healthcheck) = &config.healthcheck {
+            lifecycle.failed_probes = 0;
+            self.reactor.schedule(
+                running.started_at + duration_from_f64(healthcheck.interval),
+                self.state.name.clone(),
+                running.run_id,
+                TimerKind::Probe,
+            );
+        }
+
+        let healthy_uptime = duration_from_f64(config.healthy_uptime);
+        if config.notify || config.healthcheck.is_some() {
+            // The process is healthy once it reports being ready, or once a probe succeeds.
+            lifecycle.transition(ProcessStatus::Starting);
+            self.log(LogEventKind::Starting);
+        } else if healthy_uptime.is_zero() {
+            self.become_healthy(lifecycle);
+        } else {
+            lifecycle.transition(ProcessStatus::Starting);
+            self.log(LogEventKind::Starting);
+            self.reactor.schedule(
+                running.started_at + healthy_uptime,
+                self.state.name.clone(),
+                running.run_id,
+                TimerKind::Healthy,
+            );
+        }
+
+        lifecycle.running = Some(running);
+    }
+
+    /// Called by the reactor when the process has exited.
+    pub fn on_exit(&self, status: ExitCode) {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        lifecycle.running = None;
+        lifecycle.last_exit = Some(status);
+        lifecycle.stop_started = false;
+
+        self.log(LogEventKind::Exited(status));
+
+        if lifecycle.restart {
+            lifecycle.restart = false;
+            lifecycle.standby = false;
+            lifecycle.retry_count = 0;
+            self.spawn(&mut lifecycle);
+            return;
+        }
+
+        if lifecycle.standby {
+            lifecycle.transition(ProcessStatus::Stopped);
+            return;
+        }
+
+        let config = self.state.config.read().unwrap();
+        let retries = config.retries;
+        let should_restart = match config.restart {
+            RestartPolicy::OnFailure => {
+                lifecycle.hung || !config.exit_code.contains(&status.like_bash())
+            }
+            RestartPolicy::Always => true,
+            RestartPolicy::Never => false,
+        };
+        drop(config);
+
+        // Exiting before being healthy is a failed start, whatever the exit code, unless the
+        // process is not meant to be restarted: it has then simply run to completion. Only failed
+        // starts count towards `retries`.
+        if !should_restart {
+            lifecycle.standby = true;
+            lifecycle.transition(ProcessStatus::Exited);
+        } else if lifecycle.status == ProcessStatus::Starting {
+            lifecycle.retry_count += 1;
+            if lifecycle.retry_count > retries {
+                lifecycle.standby = true;
+                lifecycle.transition(ProcessStatus::Fatal);
+                self.log(LogEventKind::Fatal);
+            } else {
+                self.schedule_restart(&mut lifecycle);
+            }
+        } else {
+            self.schedule_restart(&mut lifecycle);
+        }
+    }
+
+    /// Marks the process as healthy once it has been up for `healthy_uptime`, once it has
+    /// reported being ready, or once its first health probe has succeeded.
+    ///
+    /// The process has then started successfully, so its previous failed starts no longer count
+    /// towards `retries`.
+    fn become_healthy(&self, lifecycle: &mut Lifecycle) {
+        lifecycle.retry_count = 0;
+        lifecycle.transition(ProcessStatus::Running);
+        self.log(LogEventKind::Started);
+        self.reactor.notify_healthy();
+    }
+
+    /// Called by the reactor when the pipe identified by `token` can be read from.
+    ///
+    /// The lines the process has written are sent to its log files.
+    pub fn on_output(&self, token: u64) {
+        let config = self.state.config.read().unwrap();
+        let lines = self
+            .state
+            .output
+            .lock()
+            .unwrap()
+            .read(token, &self.state.name);
+
+        for (stream, line) in lines {
+            if let Some(path) = config.output_file(stream) {
+                let _ = self.output_sender.send(OutputMessage::Line {
+                    path: path.to_owned(),
+                    line,
+                });
+            }
+        }
+    }
+
+    /// Called by the reactor when the process has sent notifications on its `NOTIFY_SOCKET`.
+    pub fn on_notify(&self) {
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        let Some(socket) = &lifecycle.notify else {
+            return;
+        };
+
+        for (sender, notification) in socket.receive() {
+            // Anyone can write to the socket, but only the process and its descendants are heard.
+            if lifecycle.running.is_none() || !lifecycle.is_descendant(sender) {
                 continue;
             }
+            match notification {
+                Notification::Ready => {
+                    if lifecycle.status == ProcessStatus::Starting {
+                        self.become_healthy(&mut lifecycle);
+                    }
+                }
+                Notification::Status(text) => lifecycle.status_text = Some(text),
+                Notification::Watchdog => lifecycle.keepalive = Instant::now(),
+                Notification::Stopping => {
+                    if lifecycle.running.is_some() {
+                        lifecycle.transition(ProcessStatus::Stopping);
+                    }
+                }
+                Notification::MainPid(pid) => {
+                    if !lifecycle.is_descendant(pid) {
+                        continue;
+                    }
+                    // The new main process is reparented to the supervisor once its parent exits.
+                    if let Some(running) = &mut lifecycle.running {
+                        running.pid = pid;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops the run `run_id` if it has not sent a keepalive within `watchdog_timeout`.
+    ///
+    /// Otherwise, the check is scheduled again for when the last keepalive expires.
+    fn watchdog(&self, run_id: u64) {
+        let config = self.state.config.read().unwrap();
+        let Some(timeout) = config.watchdog_timeout.map(duration_from_f64) else {
+            return;
         };
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if !lifecycle.is_monitored(run_id) {
+            return;
+        }
 
-        *state.pid.lock().unwrap() = Some(RunningProcess::started_right_now(pid));
+        let heartbeat = config.heartbeat_file.as_deref().and_then(modified_at);
+        let last = heartbeat.map_or(lifecycle.keepalive, |h| h.max(lifecycle.keepalive));
+        if last.elapsed() < timeout {
+            self.reactor.schedule(
+                last + timeout,
+                self.state.name.clone(),
+                run_id,
+                TimerKind::Watchdog,
+            );
+            return;
+        }
 
-        let has_been_stopped = Arc::new(AtomicBool::new(false));
+        self.log(LogEventKind::Hung(last.elapsed()));
+        if let Err(err) = self.stop_step(&config, &mut lifecycle, run_id, 0) {
+            self.log(LogEventKind::Failed(format!("can't stop: {err}")));
+        }
+        lifecycle.hung = true;
+        lifecycle.transition(ProcessStatus::Stopping);
+    }
 
-        if healthy_uptime.is_zero() {
-            log_sender
-                .send(LogEvent {
-                    kind: LogEventKind::Started,
-                    time: Instant::now(),
-                    name: state.name.clone(),
-                })
-                .unwrap();
-        } else {
-            log_sender
-                .send(LogEvent {
-                    kind: LogEventKind::Starting,
-                    time: Instant::now(),
-                    name: state.name.clone(),
-                })
-                .unwrap();
-
-            let name = state.name.clone();
-            let log_sender = log_sender.clone();
-            let has_been_stopped = has_been_stopped.clone();
-            std::thread::spawn(move || {
-                std::thread::sleep(healthy_uptime);
-                if !has_been_stopped.load(Relaxed) {
-                    log_sender
-                        .send(LogEvent {
-                            kind: LogEventKind::Started,
-                            time: Instant::now(),
+    /// Starts a health probe of the run `run_id`.
+    ///
+    /// Its outcome is reported to [`Process::on_probe`] by the reactor.
+    fn probe(&self, run_id: u64) {
+        let config = self.state.config.read().unwrap();
+        let Some(healthcheck) = &config.healthcheck else {
+            return;
+        };
+        {
+            let lifecycle = self.state.lifecycle.lock().unwrap();
+            if !lifecycle.is_monitored(run_id) {
+                return;
+            }
+        }
+
+        let name = self.state.name.clone();
+        let timeout = duration_from_f64(healthcheck.timeout);
+        let check = match &healthcheck.probe {
+            Probe::Exec(argv) => {
+                match health::spawn_command(argv) {
+                    Ok(pid) => {
+                        self.reactor.watch_probe(pid, name.clone(), run_id);
+                        self.reactor.schedule(
+                            Instant::now() + timeout,
                             name,
-                        })
-                        .unwrap();
+                            run_id,
+                            TimerKind::ProbeTimeout(pid),
+                        );
+                    }
+                    Err(err) => self.reactor.complete_probe(ProbeResult {
+                        name,
+                        run_id,
+                        outcome: Err(format!("can't run probe: {err}")),
+                    }),
                 }
-            });
-        }
+                return;
+            }
+            Probe::Tcp(address) => Check::Tcp(address.clone()),
+            Probe::Http(url) => Check::Http(url.clone()),
+        };
 
-        let status = wait_pid(pid).unwrap();
-        *state.pid.lock().unwrap() = None;
-        has_been_stopped.store(true, Relaxed);
+        self.reactor.run_probe(NetworkProbe {
+            name,
+            run_id,
+            check,
+            timeout,
+        });
+    }
 
-        log_sender
-            .send(LogEvent {
-                time: Instant::now(),
-                name: state.name.clone(),
-                kind: LogEventKind::Exited(status),
-            })
-            .unwrap();
-
-        match state.config.read().unwrap().restart {
-            RestartPolicy::OnFailure
-                if state
-                    .config
-                    .read()
-                    .unwrap()
-                    .exit_code
-                    .contains(&status.like_bash()) =>
-            {
-                state.observer_state.lock().unwrap().standby = true;
+    /// Called by the reactor when a health probe of the run `run_id` has completed.
+    ///
+    /// The process is unhealthy once `failures` consecutive probes have failed, and healthy again
+    /// as soon as one succeeds.
+    pub fn on_probe(&self, run_id: u64, outcome: Result<(), String>) {
+        let config = self.state.config.read().unwrap();
+        let Some(healthcheck) = &config.healthcheck else {
+            return;
+        };
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+        if !lifecycle.is_monitored(run_id) {
+            return;
+        }
+
+        match outcome {
+            Ok(()) => {
+                lifecycle.failed_probes = 0;
+                match lifecycle.status {
+                    ProcessStatus::Starting if !config.notify => {
+                        self.become_healthy(&mut lifecycle)
+                    }
+                    ProcessStatus::Unhealthy => {
+                        // The process may have been unhealthy since it started.
+                        lifecycle.retry_count = 0;
+                        lifecycle.transition(ProcessStatus::Running);
+                        self.log(LogEventKind::Healthy);
+                        self.reactor.notify_healthy();
+                    }
+                    _ => (),
+                }
             }
-            RestartPolicy::OnFailure | RestartPolicy::Always => {
-                retry_count += 1;
-                if retry_count > state.config.read().unwrap().retries {
-                    state.observer_state.lock().unwrap().standby = true;
+            Err(reason) => {
+                lifecycle.failed_probes += 1;
+                if lifecycle.failed_probes >= healthcheck.failures
+                    && lifecycle.status != ProcessStatus::Unhealthy
+                {
+                    lifecycle.transition(ProcessStatus::Unhealthy);
+                    self.log(LogEventKind::Unhealthy(reason));
+
+                    if healthcheck.action == HealthAction::Restart {
+                        if let Err(err) = self.stop_step(&config, &mut lifecycle, run_id, 0) {
+                            self.log(LogEventKind::Failed(format!("can't stop: {err}")));
+                        }
+                        lifecycle.standby = true;
+                        lifecycle.restart = true;
+                        lifecycle.transition(ProcessStatus::Stopping);
+                        return;
+                    }
                 }
             }
-            RestartPolicy::Never => {
-                state.observer_state.lock().unwrap().standby = true;
+        }
+
+        self.reactor.schedule(
+            Instant::now() + duration_from_f64(healthcheck.interval),
+            self.state.name.clone(),
+            run_id,
+            TimerKind::Probe,
+        );
+    }
+
+    /// Waits before restarting a process that has exited.
+    ///
+    /// The delay grows with the number of consecutive failed starts.
+    fn schedule_restart(&self, lifecycle: &mut Lifecycle) {
+        let delay = backoff_delay(&self.state.config.read().unwrap(), lifecycle.retry_count);
+        if delay.is_zero() {
+            self.spawn(lifecycle);
+            return;
+        }
+
+        lifecycle.backoff_id = NEXT_RUN_ID.fetch_add(1, Relaxed);
+        lifecycle.transition(ProcessStatus::Backoff);
+        self.log(LogEventKind::Backoff(delay));
+        self.reactor.schedule(
+            Instant::now() + delay,
+            self.state.name.clone(),
+            lifecycle.backoff_id,
+            TimerKind::Backoff,
+        );
+    }
+
+    /// Called by the reactor when a timer scheduled for the run `run_id` fires.
+    ///
+    /// For [`TimerKind::Backoff`], `run_id` identifies the pending restart instead.
+    pub fn on_timer(&self, kind: TimerKind, run_id: u64) {
+        match kind {
+            TimerKind::Escalate(step) => return self.escalate(run_id, step),
+            TimerKind::Probe => return self.probe(run_id),
+            TimerKind::Watchdog => return self.watchdog(run_id),
+            // The probe is killed even if the run has ended.
+            TimerKind::ProbeTimeout(pid) => return self.reactor.kill_probe(pid),
+            _ => (),
+        }
+
+        let mut lifecycle = self.state.lifecycle.lock().unwrap();
+
+        if kind == TimerKind::Backoff {
+            if lifecycle.status == ProcessStatus::Backoff && lifecycle.backoff_id == run_id {
+                self.spawn(&mut lifecycle);
+            }
+            return;
+        }
+
+        let Some(running) = lifecycle.running.as_ref() else {
+            return;
+        };
+        if running.run_id != run_id {
+            return;
+        }
+
+        match kind {
+            TimerKind::Healthy => {
+                if lifecycle.status == ProcessStatus::Starting {
+                    self.become_healthy(&mut lifecycle);
+                }
             }
+            TimerKind::Backoff
+            | TimerKind::Escalate(_)
+            | TimerKind::Probe
+            | TimerKind::ProbeTimeout(_)
+            | TimerKind::Watchdog => unreachable!(),
         }
     }
 }
 
-fn duration_from_f64(value: f64) -> Duration {
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.force_stop();
+    }
+}
+
+/// Returns the instant at which a file was last modified.
+fn modified_at(path: &Path) -> Option<Instant> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Instant::now().checked_sub(modified.elapsed().unwrap_or_default())
+}
+
+pub fn duration_from_f64(value: f64) -> Duration {
     Duration::try_from_secs_f64(value).unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(yaml: &str) -> ProgramConfig {
+        serde_yaml::from_str(&format!("command: /bin/true\n{yaml}")).unwrap()
+    }
+
+    fn name(name: &str, index: usize) -> ProcessName {
+        ProcessName {
+            name: Arc::from(name),
+            index,
+        }
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+        assert!(!glob_match("a", ""));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("web", "web"));
+        assert!(!glob_match("web", "webs"));
+        assert!(glob_match("web*", "web"));
+        assert!(glob_match("web*", "web-12"));
+        assert!(glob_match("*-1", "web-1"));
+        assert!(!glob_match("*-1", "web-12"));
+        assert!(glob_match("w?b", "web"));
+        assert!(!glob_match("w?b", "wb"));
+        assert!(glob_match("*b*b*", "abcbd"));
+        assert!(!glob_match("*b*b*", "abcd"));
+        assert!(glob_match("a**b", "ab"));
+        assert!(glob_match("*?", "x"));
+        assert!(!glob_match("*?", ""));
+    }
+
+    #[test]
+    fn process_name_matches() {
+        let web = name("web", 1);
+        assert!(web.matches("web"));
+        assert!(web.matches("web-1"));
+        assert!(web.matches("web:1"));
+        assert!(web.matches("web*"));
+        assert!(web.matches("*:1"));
+        assert!(web.matches("w?b-?"));
+        assert!(!web.matches("web-0"));
+        assert!(!web.matches("web:0"));
+        assert!(!web.matches("we"));
+        assert!(!web.matches(""));
+    }
+
+    #[test]
+    fn process_name_ending_with_index() {
+        // A program named `worker-2` has replicas named `worker-2-0`, `worker-2-1`...
+        let replica = name("worker-2", 0);
+        assert!(replica.matches("worker-2"));
+        assert!(replica.matches("worker-2-0"));
+        assert!(replica.matches("worker-2:0"));
+        assert!(!replica.matches("worker:2"));
+
+        // The replica of `worker` is not designated by the name of the `worker-2` program.
+        let other = name("worker", 2);
+        assert!(other.matches("worker-2"));
+        assert!(!other.matches("worker-2-0"));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let config = program("backoff_initial: 1\nbackoff_multiplier: 2\nbackoff_max: 60");
+        let delays: Vec<u64> = (0..=5)
+            .map(|attempt| backoff_delay(&config, attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let config = program("backoff_initial: 1\nbackoff_multiplier: 10\nbackoff_max: 30");
+        assert_eq!(backoff_delay(&config, 3), Duration::from_secs(30));
+        assert_eq!(backoff_delay(&config, u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn backoff_never_shrinks() {
+        let config = program("backoff_initial: 4\nbackoff_multiplier: 0.5");
+        assert_eq!(backoff_delay(&config, 5), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_bounds() {
+        let config = program("backoff_initial: 10\nbackoff_multiplier: 1\nbackoff_jitter: 0.5");
+        for _ in 0..100 {
+            let delay = backoff_delay(&config, 1);
+            assert!((5.0..=15.0).contains(&delay.as_secs_f64()), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn backoff_can_be_disabled() {
+        let config = program("backoff_initial: 0");
+        assert!(backoff_delay(&config, 3).is_zero());
+    }
+
+    /// Creates a process whose run is in progress and has become healthy, and returns it along
+    /// with the run.
+    fn running_process(yaml: &str) -> (Process, u64) {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let (log_sender, _) = std::sync::mpsc::channel();
+        let (output_sender, _) = std::sync::mpsc::channel();
+        let process = Process::new(
+            log_sender,
+            output_sender,
+            reactor,
+            name("web", 0),
+            program(yaml),
+        );
+
+        let running = RunningProcess::started_right_now(pid_t::MAX);
+        let run_id = running.run_id;
+        let mut lifecycle = process.state.lifecycle.lock().unwrap();
+        lifecycle.running = Some(running);
+        lifecycle.transition(ProcessStatus::Running);
+        drop(lifecycle);
+        (process, run_id)
+    }
+
+    #[test]
+    fn unhealthy_after_consecutive_failed_probes() {
+        let (process, run_id) = running_process("healthcheck: { tcp: 'localhost:1', failures: 3 }");
+        let status = || process.state.lifecycle.lock().unwrap().status;
+        let fail = || process.on_probe(run_id, Err("refused".into()));
+
+        fail();
+        fail();
+        assert_eq!(status(), ProcessStatus::Running);
+        // A successful probe starts the count over.
+        process.on_probe(run_id, Ok(()));
+        fail();
+        fail();
+        assert_eq!(status(), ProcessStatus::Running);
+        fail();
+        assert_eq!(status(), ProcessStatus::Unhealthy);
+
+        process.on_probe(run_id, Ok(()));
+        assert_eq!(status(), ProcessStatus::Running);
+        // The probes of another run are ignored.
+        for _ in 0..3 {
+            process.on_probe(run_id + 1, Err("refused".into()));
+        }
+        assert_eq!(status(), ProcessStatus::Running);
+    }
+
+    #[test]
+    fn early_exits_only_fail_when_restarted() {
+        let exit_early = |yaml: &str, code: c_int| {
+            let (process, _) = running_process(yaml);
+            process.state.lifecycle.lock().unwrap().status = ProcessStatus::Starting;
+            process.state.lifecycle.lock().unwrap().standby = false;
+            process.on_exit(ExitCode(code << 8));
+            let lifecycle = process.state.lifecycle.lock().unwrap();
+            (lifecycle.status, lifecycle.retry_count)
+        };
+
+        assert_eq!(exit_early("restart: never", 1), (ProcessStatus::Exited, 0));
+        assert_eq!(
+            exit_early("restart: on_failure", 0),
+            (ProcessStatus::Exited, 0)
+        );
+        assert_eq!(
+            exit_early("restart: on_failure", 1),
+            (ProcessStatus::Backoff, 1)
+        );
+        assert_eq!(
+            exit_early("restart: always", 0),
+            (ProcessStatus::Backoff, 1)
+        );
+        assert_eq!(
+            exit_early("restart: always\nretries: 0", 0),
+            (ProcessStatus::Fatal, 1)
+        );
+    }
+
+    #[test]
+    fn commands_unblock_signals() {
+        // The mask of the calling thread is inherited, as with the reactor's in the supervisor.
+        std::thread::spawn(|| {
+            let mut mask = unsafe { std::mem::zeroed::<libc::sigset_t>() };
+            unsafe {
+                libc::sigemptyset(&mut mask);
+                libc::sigaddset(&mut mask, libc::SIGTERM);
+                libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
+            }
+
+            let mut command = Command::new("/bin/grep");
+            command.args(["SigBlk", "/proc/self/status"]);
+            unblock_signals(&mut command);
+            let output = command.output().unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                "SigBlk:\t0000000000000000\n"
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! The line-based protocol spoken over the control socket.
+//!
+//! A client sends one command per line, exactly as it would be typed in the shell. For each
+//! command, the server answers with a header line of the form `ok <count>` or `error <count>`,
+//! followed by `<count>` lines of output.
+//!
+//! A command that keeps streaming output, like `logs -f`, first sends any number of partial
+//! replies, each with a header of the form `more <count>`, and ends with a usual header. While
+//! the output is streamed, the client can send an empty line to stop it.
+
+use std::io::{BufRead, Write};
+
+/// The path of the control socket that clients connect to unless told otherwise.
+pub const DEFAULT_SOCKET_PATH: &str = "taskmaster.sock";
+
+/// The reply to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// Whether the command succeeded.
+    pub success: bool,
+    /// The output of the command, one entry per line.
+    pub lines: Vec<String>,
+}
+
+impl Default for Reply {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reply {
+    /// Creates a new, successful, empty reply.
+    pub fn new() -> Self {
+        Self {
+            success: true,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Adds a line of output to the reply.
+    pub fn push(&mut self, line: impl Into<String>) {
+        self.lines.extend(line.into().lines().map(str::to_owned));
+    }
+
+    /// Adds a line of output to the reply and marks it as failed.
+    pub fn fail(&mut self, line: impl Into<String>) {
+        self.success = false;
+        self.push(line);
+    }
+}
+
+/// Sends a command.
+pub fn write_request(w: &mut impl Write, line: &str) -> std::io::Result<()> {
+    writeln!(w, "{}", line.trim())?;
+    w.flush()
+}
+
+/// Reads a command.
+///
+/// `None` is returned when the peer has closed the connection.
+pub fn read_request(r: &mut impl BufRead) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_owned()))
+}
+
+/// Sends the reply to a command.
+pub fn write_reply(w: &mut impl Write, reply: &Reply) -> std::io::Result<()> {
+    let status = if reply.success { "ok" } else { "error" };
+    writeln!(w, "{status} {}", reply.lines.len())?;
+    for line in &reply.lines {
+        writeln!(w, "{line}")?;
+    }
+    w.flush()
+}
+
+/// Sends a partial reply, more output following.
+pub fn write_partial(w: &mut impl Write, lines: &[String]) -> std::io::Result<()> {
+    writeln!(w, "more {}", lines.len())?;
+    for line in lines {
+        writeln!(w, "{line}")?;
+    }
+    w.flush()
+}
+
+/// Reads the reply to a command.
+///
+/// The lines of the partial replies are included in the reply.
+pub fn read_reply(r: &mut impl BufRead) -> std::io::Result<Reply> {
+    let mut partial = Vec::new();
+    let mut reply = read_streamed_reply(r, |lines| partial.extend(lines))?;
+    partial.append(&mut reply.lines);
+    reply.lines = partial;
+    Ok(reply)
+}
+
+/// Reads the reply to a command, passing the lines of each partial reply to `partial` as soon as
+/// it is received.
+pub fn read_streamed_reply(
+    r: &mut impl BufRead,
+    mut partial: impl FnMut(Vec<String>),
+) -> std::io::Result<Reply> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed reply");
+
+    loop {
+        let mut header = String::new();
+        if r.read_line(&mut header)? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let (status, count) = header.trim_end().split_once(' ').ok_or_else(invalid)?;
+        let success = match status {
+            "ok" => Some(true),
+            "error" => Some(false),
+            "more" => None,
+            _ => return Err(invalid()),
+        };
+        let count: usize = count.parse().map_err(|_| invalid())?;
+
+        let mut lines = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            line.truncate(line.trim_end_matches('\n').len());
+            lines.push(line);
+        }
+
+        match success {
+            Some(success) => return Ok(Reply { success, lines }),
+            None => partial(lines),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(success: bool, lines: &[&str]) -> Reply {
+        Reply {
+            success,
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let mut buf = Vec::new();
+        write_request(&mut buf, "  status web  \n").unwrap();
+        assert_eq!(buf, b"status web\n");
+
+        let mut r = &buf[..];
+        assert_eq!(read_request(&mut r).unwrap().as_deref(), Some("status web"));
+        assert_eq!(read_request(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        for expected in [
+            reply(true, &[]),
+            reply(true, &["a", "", "b c"]),
+            reply(false, &["Process not found: web"]),
+        ] {
+            let mut buf = Vec::new();
+            write_reply(&mut buf, &expected).unwrap();
+            assert_eq!(read_reply(&mut &buf[..]).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn push_splits_lines() {
+        let mut reply = Reply::new();
+        reply.push("a\nb");
+        reply.fail("c");
+        assert_eq!(reply, self::reply(false, &["a", "b", "c"]));
+    }
+
+    #[test]
+    fn streamed_reply() {
+        let mut buf = Vec::new();
+        write_partial(&mut buf, &["1".into(), "2".into()]).unwrap();
+        write_partial(&mut buf, &[]).unwrap();
+        write_partial(&mut buf, &["3".into()]).unwrap();
+        write_reply(&mut buf, &reply(true, &["end"])).unwrap();
+
+        let mut parts = Vec::new();
+        let end = read_streamed_reply(&mut &buf[..], |lines| parts.push(lines)).unwrap();
+        assert_eq!(parts, [vec!["1", "2"], vec![], vec!["3"]]);
+        assert_eq!(end, reply(true, &["end"]));
+
+        // Without a callback, the partial lines come first.
+        assert_eq!(
+            read_reply(&mut &buf[..]).unwrap(),
+            reply(true, &["1", "2", "3", "end"])
+        );
+    }
+
+    #[test]
+    fn malformed_replies() {
+        for input in [
+            &b""[..],
+            b"ok\n",
+            b"maybe 0\n",
+            b"ok x\n",
+            b"ok 2\nonly one\n",
+            b"more 1\n",
+        ] {
+            assert!(read_reply(&mut &input[..]).is_err(), "{input:?}");
+        }
+    }
+}
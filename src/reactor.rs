@@ -0,0 +1,644 @@
+//! The event loop driving every supervised process.
+//!
+//! A single thread waits on an `epoll` instance for signals (through a `signalfd`) and wake-up
+//! requests (through an `eventfd`), and fires the timers scheduled by the processes. Child exits
+//! are noticed through `SIGCHLD` and reaped centrally.
+//!
+//! The notification sockets and the output pipes of the processes are watched as well, each with a
+//! token of its own.
+//!
+//! Health probes report their outcome to the reactor, which passes it on to the process they
+//! check. Command probes are reaped along with the other children.
+//!
+//! The supervisor is the subreaper of every process it spawns: their descendants are reparented to
+//! it when orphaned, and attributed to the process whose group they belong to.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    ffi::c_int,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    time::Instant,
+};
+
+use crate::{
+    commands,
+    health::{NetworkProbe, ProbeResult, ProbeWorkers},
+    print_reply,
+    program::{ExitCode, Process, ProcessName, TimerKind},
+    ShutdownReason, Taskmaster,
+};
+
+/// The signals handled by the reactor.
+///
+/// They are blocked in every thread so that they are only ever received through the `signalfd`.
+const HANDLED_SIGNALS: [c_int; 4] = [libc::SIGCHLD, libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+/// The `epoll` token of the `signalfd`.
+const SIGNAL_TOKEN: u64 = 0;
+/// The `epoll` token of the `eventfd` used to wake the reactor up.
+const WAKER_TOKEN: u64 = 1;
+/// The first `epoll` token given to a file descriptor of a process.
+const FIRST_SOURCE_TOKEN: u64 = 2;
+
+/// Converts the return value of a system call into a result.
+fn cvt(ret: c_int) -> std::io::Result<c_int> {
+    if ret == -1 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// A timer scheduled for a specific run of a process.
+#[derive(Debug)]
+struct Timer {
+    /// The instant at which the timer fires.
+    deadline: Instant,
+    /// Breaks ties between timers with the same deadline, in scheduling order.
+    seq: u64,
+    /// The name of the process the timer is for.
+    name: ProcessName,
+    /// The run of the process the timer is for.
+    ///
+    /// Timers are never cancelled: a timer whose run has ended is simply ignored.
+    run_id: u64,
+    /// What to do when the timer fires.
+    kind: TimerKind,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+/// The timers waiting to fire, ordered by deadline.
+#[derive(Debug, Default)]
+struct TimerQueue {
+    heap: BinaryHeap<Reverse<Timer>>,
+    next_seq: u64,
+}
+
+/// A command probe that has not exited yet.
+#[derive(Debug)]
+struct RunningProbe {
+    /// The name of the process being probed.
+    name: ProcessName,
+    /// The run of the process being probed.
+    run_id: u64,
+    /// Whether the probe has been killed for taking too long.
+    timed_out: bool,
+}
+
+/// What a file descriptor watched on behalf of a process is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The socket on which the process sends its notifications.
+    Notify,
+    /// A pipe from which the output of the process is read.
+    Output,
+}
+
+/// Keeps a file descriptor watched by the reactor, until it is dropped.
+///
+/// It must be dropped before the file descriptor is closed.
+#[derive(Debug)]
+pub struct Registration {
+    fd: c_int,
+    token: u64,
+    reactor: Arc<Reactor>,
+}
+
+impl Registration {
+    /// Returns the `epoll` token of the file descriptor.
+    #[inline]
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        unsafe {
+            libc::epoll_ctl(
+                self.reactor.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_DEL,
+                self.fd,
+                std::ptr::null_mut(),
+            );
+        }
+        self.reactor.sources.lock().unwrap().remove(&self.token);
+    }
+}
+
+/// The event loop.
+#[derive(Debug)]
+pub struct Reactor {
+    epoll: OwnedFd,
+    signals: OwnedFd,
+    waker: OwnedFd,
+    timers: Mutex<TimerQueue>,
+    /// Whether a process has become healthy since the waiting processes were last checked.
+    healthy_pending: AtomicBool,
+    /// The command probes that are running, by PID.
+    probes: Mutex<HashMap<libc::pid_t, RunningProbe>>,
+    /// The outcomes of the probes that have not been passed on to their process yet.
+    probe_results: Mutex<Vec<ProbeResult>>,
+    /// The threads running the network probes, started with the first one.
+    probe_workers: OnceLock<ProbeWorkers>,
+    /// The file descriptors watched on behalf of the processes, and the processes they belong
+    /// to, by `epoll` token.
+    sources: Mutex<HashMap<u64, (ProcessName, Source)>>,
+    /// The token to give to the next file descriptor of a process.
+    next_source_token: AtomicU64,
+    /// Held while children are reaped, and while a child is spawned by another thread.
+    ///
+    /// When `exec` fails, the standard library reaps the child itself, and panics if the reactor
+    /// has reaped it first.
+    reaping: Mutex<()>,
+    /// Set while the shell follows the output of processes, so that an interrupt stops following
+    /// rather than shutting the supervisor down.
+    follow_interrupt: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl Reactor {
+    /// Creates a new [`Reactor`].
+    ///
+    /// This blocks the handled signals in the calling thread, and must therefore be called before
+    /// any other thread is spawned so that they inherit the signal mask.
+    pub fn new() -> std::io::Result<Self> {
+        unsafe {
+            let mut mask = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut mask);
+            for signal in HANDLED_SIGNALS {
+                libc::sigaddset(&mut mask, signal);
+            }
+            let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
+            if ret != 0 {
+                return Err(std::io::Error::from_raw_os_error(ret));
+            }
+
+            let signals = cvt(libc::signalfd(
+                -1,
+                &mask,
+                libc::SFD_CLOEXEC | libc::SFD_NONBLOCK,
+            ))?;
+            let signals = OwnedFd::from_raw_fd(signals);
+
+            let waker = cvt(libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK))?;
+            let waker = OwnedFd::from_raw_fd(waker);
+
+            let epoll = cvt(libc::epoll_create1(libc::EPOLL_CLOEXEC))?;
+            let epoll = OwnedFd::from_raw_fd(epoll);
+
+            // Orphaned descendants are reparented to the supervisor rather than to `init`.
+            cvt(libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1))?;
+
+            let reactor = Self {
+                epoll,
+                signals,
+                waker,
+                timers: Mutex::default(),
+                healthy_pending: AtomicBool::new(false),
+                probes: Mutex::default(),
+                probe_results: Mutex::default(),
+                probe_workers: OnceLock::new(),
+                sources: Mutex::default(),
+                next_source_token: AtomicU64::new(FIRST_SOURCE_TOKEN),
+                reaping: Mutex::default(),
+                follow_interrupt: Mutex::default(),
+            };
+
+            reactor.register(reactor.signals.as_raw_fd(), SIGNAL_TOKEN)?;
+            reactor.register(reactor.waker.as_raw_fd(), WAKER_TOKEN)?;
+
+            Ok(reactor)
+        }
+    }
+
+    /// Registers a file descriptor to be watched for readability.
+    fn register(&self, fd: c_int, token: u64) -> std::io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: token,
+        };
+        cvt(unsafe {
+            libc::epoll_ctl(self.epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event)
+        })?;
+        Ok(())
+    }
+
+    /// Starts watching a file descriptor of a process.
+    pub fn watch(
+        self: &Arc<Self>,
+        fd: c_int,
+        name: ProcessName,
+        source: Source,
+    ) -> std::io::Result<Registration> {
+        let token = self.next_source_token.fetch_add(1, Relaxed);
+        self.sources.lock().unwrap().insert(token, (name, source));
+        if let Err(err) = self.register(fd, token) {
+            self.sources.lock().unwrap().remove(&token);
+            return Err(err);
+        }
+        Ok(Registration {
+            fd,
+            token,
+            reactor: self.clone(),
+        })
+    }
+
+    /// Spawns a child, without it being reaped by the reactor until it has been executed.
+    pub fn spawn(&self, command: &mut Command) -> std::io::Result<Child> {
+        let _reaping = self.reaping.lock().unwrap();
+        command.spawn()
+    }
+
+    /// Makes interrupts set `interrupted` rather than shut the supervisor down, until this is
+    /// called again with `None`.
+    pub fn set_follow_interrupt(&self, interrupted: Option<Arc<AtomicBool>>) {
+        *self.follow_interrupt.lock().unwrap() = interrupted;
+    }
+
+    /// Sets the flag given to [`Reactor::set_follow_interrupt`], if any.
+    ///
+    /// Returns whether there was one.
+    fn interrupt_follow(&self) -> bool {
+        let follow_interrupt = self.follow_interrupt.lock().unwrap();
+        if let Some(interrupted) = &*follow_interrupt {
+            interrupted.store(true, Relaxed);
+        }
+        follow_interrupt.is_some()
+    }
+
+    /// Wakes the reactor up so that it takes newly scheduled timers into account.
+    fn wake(&self) {
+        let one = 1u64;
+        unsafe {
+            libc::write(
+                self.waker.as_raw_fd(),
+                &one as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+
+    /// Schedules a timer for a run of a process.
+    pub fn schedule(&self, deadline: Instant, name: ProcessName, run_id: u64, kind: TimerKind) {
+        let mut timers = self.timers.lock().unwrap();
+
+        let seq = timers.next_seq;
+        timers.next_seq += 1;
+
+        let is_earliest = timers
+            .heap
+            .peek()
+            .is_none_or(|Reverse(next)| deadline < next.deadline);
+
+        timers.heap.push(Reverse(Timer {
+            deadline,
+            seq,
+            name,
+            run_id,
+            kind,
+        }));
+
+        drop(timers);
+
+        if is_earliest {
+            self.wake();
+        }
+    }
+
+    /// Notes that a process has become healthy, so that the processes waiting for it are
+    /// started.
+    pub fn notify_healthy(&self) {
+        if !self.healthy_pending.swap(true, Relaxed) {
+            self.wake();
+        }
+    }
+
+    /// Keeps track of a command probe, so that its outcome is reported once it exits.
+    pub fn watch_probe(&self, pid: libc::pid_t, name: ProcessName, run_id: u64) {
+        self.probes.lock().unwrap().insert(
+            pid,
+            RunningProbe {
+                name,
+                run_id,
+                timed_out: false,
+            },
+        );
+    }
+
+    /// Kills a command probe that has taken too long, if it has not exited yet.
+    pub fn kill_probe(&self, pid: libc::pid_t) {
+        // Probes are reaped by the reactor thread, which fires the timeouts as well: a PID that is
+        // still watched has not been reused.
+        let mut probes = self.probes.lock().unwrap();
+        if let Some(probe) = probes.get_mut(&pid) {
+            probe.timed_out = true;
+            unsafe { libc::kill(-pid, libc::SIGKILL) };
+        }
+    }
+
+    /// Runs a network probe in the background.
+    pub fn run_probe(self: &Arc<Self>, probe: NetworkProbe) {
+        self.probe_workers
+            .get_or_init(|| ProbeWorkers::spawn(self.clone()))
+            .submit(probe);
+    }
+
+    /// Reports the outcome of a probe.
+    pub fn complete_probe(&self, result: ProbeResult) {
+        self.probe_results.lock().unwrap().push(result);
+        self.wake();
+    }
+
+    /// Returns the number of milliseconds until the next timer fires, or `-1` if there is none.
+    fn next_timeout(&self) -> c_int {
+        let timers = self.timers.lock().unwrap();
+        match timers.heap.peek() {
+            // Round up so that the timer has expired when we wake up.
+            Some(Reverse(next)) => {
+                let remaining = next.deadline.saturating_duration_since(Instant::now());
+                remaining.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int
+            }
+            None => -1,
+        }
+    }
+
+    /// Removes the timers that have expired.
+    fn expired_timers(&self) -> Vec<Timer> {
+        let now = Instant::now();
+        let mut timers = self.timers.lock().unwrap();
+        let mut expired = Vec::new();
+        while timers
+            .heap
+            .peek()
+            .is_some_and(|Reverse(next)| next.deadline <= now)
+        {
+            expired.push(timers.heap.pop().unwrap().0);
+        }
+        expired
+    }
+
+    /// Reads the signals that have been received.
+    fn pending_signals(&self) -> Vec<c_int> {
+        let mut signals = Vec::new();
+        loop {
+            let mut info = unsafe { std::mem::zeroed::<libc::signalfd_siginfo>() };
+            let ret = unsafe {
+                libc::read(
+                    self.signals.as_raw_fd(),
+                    &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                    std::mem::size_of::<libc::signalfd_siginfo>(),
+                )
+            };
+            if ret != std::mem::size_of::<libc::signalfd_siginfo>() as isize {
+                break;
+            }
+            signals.push(info.ssi_signo as c_int);
+        }
+        signals
+    }
+
+    /// Clears the wake-up requests.
+    fn drain_waker(&self) {
+        let mut value = 0u64;
+        unsafe {
+            libc::read(
+                self.waker.as_raw_fd(),
+                &mut value as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+
+    /// Runs the event loop forever.
+    pub fn run(&self, taskmaster: &Arc<RwLock<Taskmaster>>) -> ! {
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 16];
+
+        loop {
+            let count = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    events.len() as c_int,
+                    self.next_timeout(),
+                )
+            };
+
+            for event in &events[..count.max(0) as usize] {
+                match event.u64 {
+                    SIGNAL_TOKEN => {
+                        for signal in self.pending_signals() {
+                            handle_signal(self, taskmaster, signal);
+                        }
+                    }
+                    WAKER_TOKEN => self.drain_waker(),
+                    token => {
+                        let Some((name, source)) =
+                            self.sources.lock().unwrap().get(&token).cloned()
+                        else {
+                            continue;
+                        };
+                        let taskmaster = taskmaster.read().unwrap();
+                        if let Some(process) = taskmaster.get_process_by_process_name(&name) {
+                            match source {
+                                Source::Notify => process.on_notify(),
+                                Source::Output => process.on_output(token),
+                            }
+                        }
+                    }
+                }
+            }
+
+            for timer in self.expired_timers() {
+                let taskmaster = taskmaster.read().unwrap();
+                if let Some(process) = taskmaster.get_process_by_process_name(&timer.name) {
+                    process.on_timer(timer.kind, timer.run_id);
+                }
+            }
+
+            let results = std::mem::take(&mut *self.probe_results.lock().unwrap());
+            for result in results {
+                let taskmaster = taskmaster.read().unwrap();
+                if let Some(process) = taskmaster.get_process_by_process_name(&result.name) {
+                    process.on_probe(result.run_id, result.outcome);
+                }
+            }
+
+            if self.healthy_pending.swap(false, Relaxed) {
+                taskmaster.read().unwrap().start_waiting_processes();
+            }
+        }
+    }
+}
+
+/// Handles a signal received by the supervisor.
+fn handle_signal(reactor: &Reactor, taskmaster: &Arc<RwLock<Taskmaster>>, signal: c_int) {
+    match signal {
+        libc::SIGCHLD => reap_children(reactor, taskmaster),
+        libc::SIGINT if reactor.interrupt_follow() => (),
+        libc::SIGHUP => {
+            println!("Hangup received, reloading config");
+            // Reloading waits for processes to exit, which the reactor must notice.
+            let taskmaster = taskmaster.clone();
+            std::thread::spawn(move || print_reply(&commands::execute(&taskmaster, "reload").0));
+        }
+        _ => taskmaster
+            .read()
+            .unwrap()
+            .request_shutdown(ShutdownReason::Signal(signal)),
+    }
+}
+
+/// Reaps every child that has exited, and notifies the process it belonged to.
+fn reap_children(reactor: &Reactor, taskmaster: &RwLock<Taskmaster>) {
+    let mut exited = Vec::new();
+    loop {
+        let mut status = 0;
+        let pid = {
+            let _reaping = reactor.reaping.lock().unwrap();
+            unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) }
+        };
+        if pid <= 0 {
+            break;
+        }
+
+        if let Some(probe) = reactor.probes.lock().unwrap().remove(&pid) {
+            let status = ExitCode(status);
+            let outcome = if probe.timed_out {
+                Err("probe timed out".into())
+            } else if status.like_bash() != 0 {
+                Err(format!("probe {status}"))
+            } else {
+                Ok(())
+            };
+            reactor.probe_results.lock().unwrap().push(ProbeResult {
+                name: probe.name,
+                run_id: probe.run_id,
+                outcome,
+            });
+            continue;
+        }
+
+        exited.push((pid, status));
+    }
+    if exited.is_empty() {
+        return;
+    }
+
+    let taskmaster = taskmaster.read().unwrap();
+
+    // The children of the exited processes have been reparented to the supervisor. They must be
+    // attributed before a new run changes the group of their process.
+    adopt_orphans(&taskmaster);
+
+    for (pid, status) in exited {
+        // A child that no process claims belonged to a process that has since been removed, or
+        // is an orphan that exited before being adopted.
+        if let Some(process) = taskmaster.processes.iter().find(|p| p.pid() == Some(pid)) {
+            process.on_exit(ExitCode(status));
+        } else {
+            taskmaster.processes.iter().any(|p| p.release_stray(pid));
+        }
+    }
+}
+
+/// Attributes the orphans reparented to the supervisor to the processes they descend from.
+///
+/// An orphan belongs to the process whose group it is in. Those that left the group, such as
+/// daemons that called `setsid`, cannot be attributed: they are not stopped with their process,
+/// and are only reaped once they exit.
+fn adopt_orphans(taskmaster: &Taskmaster) {
+    let groups: HashMap<libc::pid_t, &Process> = taskmaster
+        .processes
+        .iter()
+        .filter_map(|process| Some((process.group()?, process)))
+        .collect();
+
+    for pid in supervisor_children() {
+        let Some((_, group)) = parent_and_group(pid) else {
+            continue;
+        };
+        if let Some(process) = groups.get(&group) {
+            process.adopt(pid, group);
+        }
+    }
+}
+
+/// Lists the children of the supervisor, the orphans it adopted among them.
+///
+/// They are read from `/proc/self/task/*/children`, or found by scanning `/proc` on kernels that
+/// do not provide it.
+fn supervisor_children() -> Vec<libc::pid_t> {
+    let parse = |text: &str| -> Vec<libc::pid_t> {
+        text.split_whitespace()
+            .filter_map(|pid| pid.parse().ok())
+            .collect()
+    };
+
+    let mut children = Vec::new();
+    if let Ok(tasks) = std::fs::read_dir("/proc/self/task") {
+        for task in tasks.flatten() {
+            match std::fs::read_to_string(task.path().join("children")) {
+                Ok(text) => children.extend(parse(&text)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    return scan_children();
+                }
+                Err(_) => (),
+            }
+        }
+    }
+    children
+}
+
+/// Finds the children of the supervisor by reading the parent of every process in `/proc`.
+fn scan_children() -> Vec<libc::pid_t> {
+    let supervisor = std::process::id() as libc::pid_t;
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|&pid| parent_and_group(pid).is_some_and(|(parent, _)| parent == supervisor))
+        .collect()
+}
+
+/// Reads the parent and the process group of a process from `/proc`.
+///
+/// Nothing is returned for a process that has already exited and only waits to be reaped.
+fn parent_and_group(pid: libc::pid_t) -> Option<(libc::pid_t, libc::pid_t)> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The name of the executable is between parentheses and may contain anything.
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    if fields.next()? == "Z" {
+        return None;
+    }
+    let parent = fields.next()?.parse().ok()?;
+    let group = fields.next()?.parse().ok()?;
+    Some((parent, group))
+}
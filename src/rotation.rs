@@ -0,0 +1,238 @@
+//! Log files rotated by the supervisor.
+//!
+//! A rotated file is renamed to `<file>.1`, after the previous backups have been shifted, and a new
+//! file is created in its place. Files are only ever rotated between two lines, and only regular
+//! files are: a device such as `/dev/null`, or a path that is a symbolic link, is left as is.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{config::Rotation, output::local_time};
+
+/// Returns the local day of a time, as its year and its day in the year.
+fn local_day(time: SystemTime) -> (i32, i32) {
+    let tm = local_time(time);
+    (tm.tm_year, tm.tm_yday)
+}
+
+/// Returns the path of the `index`-th backup of a log file.
+fn backup_path(path: &Path, index: usize, gzip: bool) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{index}"));
+    if gzip {
+        backup.push(".gz");
+    }
+    PathBuf::from(backup)
+}
+
+/// Compresses a file with gzip, replacing it with `<file>.gz`.
+fn compress(path: &Path) -> io::Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+
+    let mut encoder = GzEncoder::new(File::create(compressed)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(path)
+}
+
+/// Ignores the error of an operation on a file that does not exist.
+fn ignore_not_found(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// A log file, rotated according to its [`Rotation`].
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    /// The size of the file.
+    len: u64,
+    /// The local day on which the file was last written to.
+    day: (i32, i32),
+    /// Whether the last byte written ends a line.
+    at_line_start: bool,
+    /// Whether the path is a regular file, the only kind that is rotated.
+    regular: bool,
+    rotation: Rotation,
+}
+
+impl LogFile {
+    /// Opens a log file for appending, creating it and its directory if needed.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let is_link = std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            len: metadata.len(),
+            // A file left by a previous day is rotated before being written to.
+            day: local_day(metadata.modified().unwrap_or_else(|_| SystemTime::now())),
+            at_line_start: true,
+            regular: metadata.file_type().is_file() && !is_link,
+            rotation,
+        })
+    }
+
+    /// Returns the path of the file.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flushes the data written to the file to the disk.
+    #[inline]
+    pub fn sync_data(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Changes when the file is rotated.
+    #[inline]
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    /// Closes the file and opens it again, in case it has been moved by an external tool.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        *self = Self::open(&self.path, self.rotation)?;
+        Ok(())
+    }
+
+    /// Writes `buf` to the file, rotating it first if needed.
+    ///
+    /// If the file cannot be rotated, `buf` is still written to it.
+    pub fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let rotated = if self.at_line_start && self.must_rotate(buf.len()) {
+            self.rotate()
+        } else {
+            Ok(())
+        };
+
+        self.file.write_all(buf)?;
+        self.len += buf.len() as u64;
+        self.day = local_day(SystemTime::now());
+        if let Some(&last) = buf.last() {
+            self.at_line_start = last == b'\n';
+        }
+        rotated
+    }
+
+    /// Returns whether the file must be rotated before `incoming` more bytes are written to it.
+    fn must_rotate(&self, incoming: usize) -> bool {
+        if !self.regular {
+            return false;
+        }
+        let too_large = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| self.len > 0 && self.len + incoming as u64 > max);
+        let new_day = self.rotation.daily && local_day(SystemTime::now()) != self.day;
+        too_large || new_day
+    }
+
+    /// Moves the file to its first backup, and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let Rotation { backups, gzip, .. } = self.rotation;
+
+        if backups == 0 {
+            self.file.set_len(0)?;
+            self.len = 0;
+            return Ok(());
+        }
+
+        // Shift the backups, the oldest one being overwritten.
+        for index in (1..backups).rev() {
+            ignore_not_found(std::fs::rename(
+                backup_path(&self.path, index, gzip),
+                backup_path(&self.path, index + 1, gzip),
+            ))?;
+        }
+
+        let first = backup_path(&self.path, 1, false);
+        std::fs::rename(&self.path, &first)?;
+        self.reopen()?;
+
+        // The new file is already in place, so a failure only leaves the backup uncompressed.
+        if gzip {
+            compress(&first)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a new empty directory for a test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("taskmaster-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rotation(max_bytes: u64) -> Rotation {
+        Rotation {
+            max_bytes: Some(max_bytes),
+            daily: false,
+            backups: 2,
+            gzip: false,
+        }
+    }
+
+    #[test]
+    fn rotates_when_too_large() {
+        let dir = temp_dir("rotates");
+        let path = dir.join("out.log");
+        let mut file = LogFile::open(&path, rotation(8)).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "four\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.log.1")).unwrap(),
+            "three\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.log.2")).unwrap(),
+            "one\ntwo\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_rotates_regular_files() {
+        let mut file = LogFile::open(Path::new("/dev/null"), rotation(1)).unwrap();
+        file.write_all(b"one\n").unwrap();
+        file.write_all(b"two\n").unwrap();
+        assert!(!Path::new("/dev/null.1").exists());
+
+        let dir = temp_dir("symlink");
+        let (target, link) = (dir.join("target.log"), dir.join("link.log"));
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let mut file = LogFile::open(&link, rotation(1)).unwrap();
+        file.write_all(b"one\n").unwrap();
+        file.write_all(b"two\n").unwrap();
+
+        assert!(link.is_symlink());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "one\ntwo\n");
+        assert!(!dir.join("link.log.1").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
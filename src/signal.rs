@@ -0,0 +1,273 @@
+//! Signal names and numbers.
+
+use std::{ffi::c_int, fmt::Display, str::FromStr};
+
+/// The names of the standard signals, without their `SIG` prefix.
+///
+/// When several names refer to the same signal, the first one is used to display it.
+const NAMES: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("IOT", libc::SIGIOT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("POLL", libc::SIGPOLL),
+    ("PWR", libc::SIGPWR),
+    ("SYS", libc::SIGSYS),
+];
+
+/// A signal that can be sent to a process.
+///
+/// It is parsed from its name, with or without the `SIG` prefix, or from its number. Real-time
+/// signals are named relative to `SIGRTMIN` or `SIGRTMAX`, as in `SIGRTMIN+3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signal(pub c_int);
+
+impl Signal {
+    pub const INT: Signal = Signal(libc::SIGINT);
+    pub const KILL: Signal = Signal(libc::SIGKILL);
+
+    /// Creates a [`Signal`] from its number, if it is a valid signal.
+    pub fn from_raw(raw: c_int) -> Option<Self> {
+        (1..=libc::SIGRTMAX()).contains(&raw).then_some(Self(raw))
+    }
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Self::INT
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+
+        let name = if let Some((name, _)) = NAMES.iter().find(|(_, raw)| *raw == self.0) {
+            format!("SIG{name}")
+        } else if self.0 == min {
+            "SIGRTMIN".into()
+        } else if self.0 == max {
+            "SIGRTMAX".into()
+        } else if self.0 > min && self.0 <= (min + max) / 2 {
+            format!("SIGRTMIN+{}", self.0 - min)
+        } else if self.0 > min && self.0 < max {
+            format!("SIGRTMAX-{}", max - self.0)
+        } else {
+            format!("signal {}", self.0)
+        };
+
+        f.pad(&name)
+    }
+}
+
+/// An error that can occur when parsing a [`Signal`].
+#[derive(Debug)]
+pub struct ParseSignalError(String);
+
+impl Display for ParseSignalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown signal `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseSignalError {}
+
+impl FromStr for Signal {
+    type Err = ParseSignalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseSignalError(s.to_owned());
+
+        if let Ok(raw) = s.parse::<c_int>() {
+            return Self::from_raw(raw).ok_or_else(error);
+        }
+
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+        if let Some((_, raw)) = NAMES.iter().find(|(n, _)| *n == name) {
+            return Ok(Self(*raw));
+        }
+
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+        let raw = match name {
+            "RTMIN" => min,
+            "RTMAX" => max,
+            _ => {
+                let parse = |offset: &str| offset.parse::<c_int>().map_err(|_| error());
+                let raw = if let Some(offset) = name.strip_prefix("RTMIN+") {
+                    min.checked_add(parse(offset)?)
+                } else if let Some(offset) = name.strip_prefix("RTMAX-") {
+                    max.checked_sub(parse(offset)?)
+                } else {
+                    None
+                };
+                raw.ok_or_else(error)?
+            }
+        };
+
+        if (min..=max).contains(&raw) {
+            Ok(Self(raw))
+        } else {
+            Err(error())
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Signal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct SignalVisitor;
+
+        impl serde::de::Visitor<'_> for SignalVisitor {
+            type Value = Signal;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a signal name or number")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                c_int::try_from(value)
+                    .ok()
+                    .and_then(Signal::from_raw)
+                    .ok_or_else(|| E::custom(format!("invalid signal number {value}")))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                c_int::try_from(value)
+                    .ok()
+                    .and_then(Signal::from_raw)
+                    .ok_or_else(|| E::custom(format!("invalid signal number {value}")))
+            }
+        }
+
+        deserializer.deserialize_any(SignalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<c_int> {
+        s.parse::<Signal>().ok().map(|signal| signal.0)
+    }
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(parse("TERM"), Some(libc::SIGTERM));
+        assert_eq!(parse("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(parse("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(parse("Kill"), Some(libc::SIGKILL));
+        assert_eq!(parse("CLD"), Some(libc::SIGCHLD));
+        assert_eq!(parse("SIG"), None);
+        assert_eq!(parse("SIGSIGTERM"), None);
+        assert_eq!(parse("BOGUS"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse("15"), Some(15));
+        assert_eq!(parse("1"), Some(1));
+        assert_eq!(parse(&libc::SIGRTMAX().to_string()), Some(libc::SIGRTMAX()));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("-9"), None);
+        assert_eq!(parse(&(libc::SIGRTMAX() + 1).to_string()), None);
+        assert_eq!(parse("99999999999"), None);
+    }
+
+    #[test]
+    fn parse_realtime() {
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+        assert_eq!(parse("RTMIN"), Some(min));
+        assert_eq!(parse("SIGRTMAX"), Some(max));
+        assert_eq!(parse("SIGRTMIN+0"), Some(min));
+        assert_eq!(parse("sigrtmin+3"), Some(min + 3));
+        assert_eq!(parse("SIGRTMAX-2"), Some(max - 2));
+        assert_eq!(parse(&format!("RTMIN+{}", max - min)), Some(max));
+        assert_eq!(parse(&format!("RTMAX-{}", max - min)), Some(min));
+    }
+
+    #[test]
+    fn parse_realtime_out_of_range() {
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+        assert_eq!(parse(&format!("RTMIN+{}", max - min + 1)), None);
+        assert_eq!(parse(&format!("RTMAX-{}", max - min + 1)), None);
+        assert_eq!(parse("RTMIN+-1"), None);
+        assert_eq!(parse("RTMAX--1"), None);
+        assert_eq!(parse("RTMIN+2147483647"), None);
+        assert_eq!(parse("RTMAX-2147483647"), None);
+        assert_eq!(parse("RTMAX--2147483648"), None);
+        assert_eq!(parse("RTMIN+"), None);
+        assert_eq!(parse("RTMIN-1"), None);
+        assert_eq!(parse("RTMAX+1"), None);
+    }
+
+    #[test]
+    fn display() {
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+        assert_eq!(Signal(libc::SIGTERM).to_string(), "SIGTERM");
+        assert_eq!(Signal(libc::SIGCHLD).to_string(), "SIGCHLD");
+        assert_eq!(Signal(libc::SIGIOT).to_string(), "SIGABRT");
+        assert_eq!(Signal(min).to_string(), "SIGRTMIN");
+        assert_eq!(Signal(max).to_string(), "SIGRTMAX");
+        assert_eq!(Signal(min + 1).to_string(), "SIGRTMIN+1");
+        assert_eq!(Signal(max - 1).to_string(), "SIGRTMAX-1");
+        assert_eq!(Signal(0).to_string(), "signal 0");
+        assert_eq!(format!("{:>8}", Signal::INT), "  SIGINT");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for raw in 1..=libc::SIGRTMAX() {
+            let name = Signal(raw).to_string();
+            if name.starts_with("signal ") {
+                continue;
+            }
+            assert_eq!(parse(&name), Some(raw), "{name}");
+        }
+    }
+}
@@ -0,0 +1,23 @@
+/*
+    This program is used to test that stopping a program also stops its
+    descendants.
+
+    It forks a few children that sleep forever, then waits for them. When
+    stopped as a group, none of them should survive; otherwise the children
+    are left running once the parent is gone.
+*/
+
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(void) {
+  for (int i = 0; i < 3; i++) {
+    if (fork() == 0) {
+      while (1) {
+        pause();
+      }
+    }
+  }
+  while (wait(NULL) > 0) {
+  }
+}
@@ -0,0 +1,57 @@
+/*
+    This program is used to test health checks.
+
+    It listens on the TCP port given as its first argument and answers every
+    connection with an empty HTTP response. Receiving SIGUSR1 makes it stop
+    listening while it keeps running, so that its health probes start failing.
+*/
+
+#include <arpa/inet.h>
+#include <signal.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+static volatile sig_atomic_t deaf = 0;
+
+static void on_usr1(int sig) {
+  (void)sig;
+  deaf = 1;
+}
+
+int main(int argc, char **argv) {
+  struct sigaction action = {0};
+  action.sa_handler = on_usr1;
+  sigaction(SIGUSR1, &action, NULL);
+
+  int server = socket(AF_INET, SOCK_STREAM, 0);
+  int one = 1;
+  setsockopt(server, SOL_SOCKET, SO_REUSEADDR, &one, sizeof(one));
+
+  struct sockaddr_in addr = {0};
+  addr.sin_family = AF_INET;
+  addr.sin_port = htons(argc > 1 ? atoi(argv[1]) : 4242);
+  addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+  if (bind(server, (struct sockaddr *)&addr, sizeof(addr)) != 0 ||
+      listen(server, 16) != 0) {
+    return 1;
+  }
+
+  const char *response = "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n";
+  char request[4096];
+  while (!deaf) {
+    int client = accept(server, NULL, NULL);
+    if (client < 0) {
+      continue;
+    }
+    read(client, request, sizeof(request));
+    write(client, response, strlen(response));
+    close(client);
+  }
+
+  close(server);
+  while (1) {
+    pause();
+  }
+}
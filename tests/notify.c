@@ -0,0 +1,74 @@
+/*
+    This program is used to test readiness notifications.
+
+    It reports its progress on the socket given in NOTIFY_SOCKET, as a daemon
+    using sd_notify would: it only reports being ready after a while, then
+    keeps sending keepalives. On SIGTERM, it reports that it is stopping
+    before exiting.
+
+    Receiving SIGUSR1 makes it hang: it stops sending keepalives, but keeps
+    running.
+*/
+
+#include <signal.h>
+#include <stddef.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/un.h>
+#include <unistd.h>
+
+static int fd = -1;
+static struct sockaddr_un addr;
+static socklen_t addr_len;
+
+static volatile sig_atomic_t stopping = 0;
+static volatile sig_atomic_t hung = 0;
+
+static void on_term(int sig) {
+  (void)sig;
+  stopping = 1;
+}
+
+static void on_usr1(int sig) {
+  (void)sig;
+  hung = 1;
+}
+
+static void notify(const char *message) {
+  if (fd >= 0) {
+    sendto(fd, message, strlen(message), 0, (struct sockaddr *)&addr,
+           addr_len);
+  }
+}
+
+int main(void) {
+  const char *path = getenv("NOTIFY_SOCKET");
+  if (path != NULL && strlen(path) < sizeof(addr.sun_path)) {
+    fd = socket(AF_UNIX, SOCK_DGRAM, 0);
+    addr.sun_family = AF_UNIX;
+    strcpy(addr.sun_path, path);
+    // A leading `@` designates the abstract namespace.
+    if (path[0] == '@') {
+      addr.sun_path[0] = '\0';
+    }
+    addr_len = offsetof(struct sockaddr_un, sun_path) + strlen(path);
+  }
+
+  signal(SIGTERM, on_term);
+  signal(SIGUSR1, on_usr1);
+
+  notify("STATUS=initializing");
+  sleep(2);
+  notify("READY=1\nSTATUS=serving");
+
+  while (!stopping) {
+    if (!hung) {
+      notify("WATCHDOG=1");
+    }
+    sleep(1);
+  }
+
+  notify("STOPPING=1\nSTATUS=shutting down");
+  sleep(1);
+}